@@ -0,0 +1,53 @@
+use TSPL::Parser;
+use std::hint::black_box;
+use std::time::Instant;
+
+TSPL::new_parser!(BenchParser);
+
+/// Runs `f` `iters` times and prints the average time per run.
+fn bench(name: &str, iters: u32, mut f: impl FnMut()) {
+  let start = Instant::now();
+  for _ in 0..iters {
+    f();
+  }
+  println!("{:<24} {:>10.2?}/iter", name, start.elapsed() / iters);
+}
+
+fn main() {
+  let names = "abc_def.ghi-jkl/mno$123 ".repeat(10_000);
+  let trivia = "  \t\n    // a comment line\r\n".repeat(10_000) + "x";
+
+  bench("take_while", 100, || {
+    let mut p = BenchParser::new(&names);
+    while !p.is_eof() {
+      black_box(p.take_while(TSPL::is_name_char));
+      p.advance_one();
+    }
+  });
+
+  bench("take_while_ascii", 100, || {
+    let mut p = BenchParser::new(&names);
+    while !p.is_eof() {
+      black_box(p.take_while_ascii(|b| TSPL::is_name_char(b as char)));
+      p.advance_one();
+    }
+  });
+
+  bench("skip_trivia (char loop)", 100, || {
+    let mut p = BenchParser::new(&trivia);
+    loop {
+      match p.peek_one() {
+        Some(c) if c.is_ascii_whitespace() => { p.advance_one(); }
+        Some('/') if p.starts_with("//") => { p.consume_line(); }
+        _ => break,
+      }
+    }
+    black_box(p.index);
+  });
+
+  bench("skip_trivia", 100, || {
+    let mut p = BenchParser::new(&trivia);
+    p.skip_trivia();
+    black_box(p.index);
+  });
+}
@@ -1,4 +1,4 @@
-use TSPL::Parser;
+use TSPL::{Parser, ParseError};
 use std::fmt;
 
 enum Term {
@@ -10,7 +10,7 @@ enum Term {
 TSPL::new_parser!(TermParser);
 
 impl<'i> TermParser<'i> {
-  fn parse(&mut self) -> Result<Term, String> {
+  fn parse(&mut self) -> Result<Term, ParseError> {
     self.skip_trivia();
     match self.peek_one() {
       Some('λ') => {
@@ -46,7 +46,7 @@ impl fmt::Debug for Term {
 
 fn main() {
   let mut parser = TermParser::new("λx(λy(x y) λz z)");
-  match parser.parse() {
+  match parser.parse_all(|p| p.parse()) {
     Ok(term) => println!("Parsed: {:?}", term),
     Err(err) => eprintln!("{}", err),
   }
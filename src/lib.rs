@@ -1,14 +1,37 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+#![allow(non_snake_case)]
+
+extern crate alloc;
+
+use alloc::borrow::{Cow, ToOwned};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+
+#[cfg(feature = "std")]
 use highlight_error::{*};
 
 #[macro_export]
 macro_rules! new_parser {
   ($Parser:ident) => {
+    $crate::new_parser!($Parser, $crate::ParseError);
+  };
+  ($Parser:ident, $Error:ty) => {
     pub struct $Parser<'i> {
       input: &'i str,
       index: usize,
+      depth: usize,
+      max_depth: usize,
+      token_start: usize,
+      trivia: (usize, usize),
+      steps: usize,
+      budget: usize,
     }
 
     impl<'i> Parser<'i> for $Parser<'i> {
+      type Error = $Error;
+
       fn input(&mut self) -> &'i str {
         &self.input
       }
@@ -16,16 +39,54 @@ macro_rules! new_parser {
       fn index(&mut self) -> &mut usize {
         &mut self.index
       }
+
+      fn depth(&mut self) -> &mut usize {
+        &mut self.depth
+      }
+
+      fn max_depth(&mut self) -> &mut usize {
+        &mut self.max_depth
+      }
+
+      fn token_start(&mut self) -> &mut usize {
+        &mut self.token_start
+      }
+
+      fn trivia(&mut self) -> &mut (usize, usize) {
+        &mut self.trivia
+      }
+
+      fn steps(&mut self) -> &mut usize {
+        &mut self.steps
+      }
+
+      fn budget(&mut self) -> &mut usize {
+        &mut self.budget
+      }
     }
 
+    #[allow(dead_code)]
     impl<'i> $Parser<'i> {
       pub fn new(input: &'i str) -> Self {
-        Self { input, index: 0 }
+        Self { input, index: 0, depth: 0, max_depth: $crate::DEFAULT_MAX_DEPTH, token_start: 0, trivia: (0, 0), steps: 0, budget: usize::MAX }
+      }
+
+      pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+      }
+
+      pub fn with_budget(mut self, budget: usize) -> Self {
+        self.budget = budget;
+        self
       }
     }
   }
 }
 
+/// Default nesting limit enforced by `Parser::enter`.
+pub const DEFAULT_MAX_DEPTH: usize = 256;
+
 #[derive(Debug, Clone, Hash)]
 pub struct ParseError {
   /// Byte-indexed span of the parsing error.
@@ -42,6 +103,26 @@ impl ParseError {
       message: message.into()
     }
   }
+
+  /// Merges two errors into one spanning both, with both messages.
+  pub fn merge(self, other: ParseError) -> ParseError {
+    let span = (self.span.0.min(other.span.0), self.span.1.max(other.span.1));
+    ParseError::new(span, format!("{}\n{}", self.message, other.message))
+  }
+
+  /// Formats the error on a single line without ANSI codes or context, like `parse error at 12: expected name`.
+  /// The expectation and information are recovered from the message built by `expected`/`expected_and`.
+  pub fn oneline(&self) -> String {
+    let text = strip_ansi(&self.message);
+    let field = |key: &str| text.lines().find_map(|line| line.trim().strip_prefix(key)).map(str::trim);
+    let detail = match (field("- expected:"), field("- information:")) {
+      (Some(exp), Some(info)) => format!("expected {} ({})", exp, info),
+      (Some(exp), None) => format!("expected {}", exp),
+      (None, Some(info)) => info.to_owned(),
+      (None, None) => text.lines().map(str::trim).find(|line| !line.is_empty() && *line != "PARSE_ERROR").unwrap_or_default().to_owned(),
+    };
+    format!("parse error at {}: {}", self.span.0, detail)
+  }
 }
 
 impl From<ParseError> for String {
@@ -50,33 +131,313 @@ impl From<ParseError> for String {
   }
 }
 
-impl std::fmt::Display for ParseError {
-  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl core::fmt::Display for ParseError {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
     self.message.fmt(f)
   }
 }
 
+#[cfg(feature = "std")]
 impl std::error::Error for ParseError {}
 
+/// Highlights an error span within its source line.
+/// Lightweight stand-in for `highlight_error` on builds without `std`.
+#[cfg(not(feature = "std"))]
+fn highlight_error(ini_idx: usize, end_idx: usize, file: &str) -> String {
+  let ini_idx = ini_idx.min(file.len());
+  let mut end_idx = end_idx.clamp(ini_idx, file.len());
+  while !file.is_char_boundary(end_idx) {
+    end_idx += 1;
+  }
+  let lin_ini = file[..ini_idx].rfind('\n').map_or(0, |i| i + 1);
+  let lin_end = file[end_idx..].find('\n').map_or(file.len(), |i| end_idx + i);
+  let line = file[..lin_ini].matches('\n').count() + 1;
+  format!(" {} | {}\x1b[4m\x1b[31m{}\x1b[0m{}", line, &file[lin_ini..ini_idx], &file[ini_idx..end_idx], &file[end_idx..lin_end])
+}
+
+/// A location in the input, with 1-based line and column (counted in chars).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Position {
+  pub byte: usize,
+  pub line: usize,
+  pub col: usize,
+}
+
+/// Result of `Parser::parse_tuple_or_paren`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TupleOrParen<T> {
+  /// A parenthesized item, like `(a)`.
+  Paren(T),
+  /// A tuple, like `()`, `(a,)` or `(a, b)`.
+  Tuple(Vec<T>),
+}
+
+/// Token produced by `Parser::layout_tokens`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LayoutToken<'i> {
+  /// The line is indented more than the previous one.
+  Indent,
+  /// An indentation level was closed.
+  Dedent,
+  /// The end of a non-blank line.
+  Newline,
+  /// A line's text, without its indentation and terminator.
+  Content(&'i str),
+}
+
+/// Associativity of a binary operator in `Parser::parse_binops`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Assoc {
+  /// Groups like `(a - b) - c`.
+  Left,
+  /// Groups like `a ^ (b ^ c)`.
+  Right,
+}
+
+/// Placement of a comment collected by `Parser::skip_trivia_classified`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CommentKind {
+  /// A comment on its own line, attached to what follows it.
+  Leading,
+  /// A comment after code on the same line, attached to what precedes it.
+  Trailing,
+}
+
+/// Part of a template parsed by `Parser::parse_brace_template`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TemplatePart {
+  /// Literal text, with `{{` and `}}` already unescaped.
+  Literal(String),
+  /// A placeholder name, like `x` in `{x}`.
+  Placeholder(String),
+}
+
+/// Computes the terminal display width of a text, counting wide chars as 2 columns
+/// and combining marks as 0. Useful to align markers under an error.
+#[cfg(feature = "width")]
+pub fn display_width(text: &str) -> usize {
+  unicode_width::UnicodeWidthStr::width(text)
+}
+
+/// Orders a span's ends so the start comes first.
+fn canonical_span(span: (usize, usize)) -> (usize, usize) {
+  (span.0.min(span.1), span.0.max(span.1))
+}
+
+/// Checks if two spans share at least one byte. Adjacent spans, like `(0, 2)` and `(2, 4)`, don't overlap.
+pub fn spans_overlap(a: (usize, usize), b: (usize, usize)) -> bool {
+  let (a, b) = (canonical_span(a), canonical_span(b));
+  a.0 < b.1 && b.0 < a.1
+}
+
+/// Checks if `inner` lies entirely within `outer`.
+pub fn span_contains(outer: (usize, usize), inner: (usize, usize)) -> bool {
+  let (outer, inner) = (canonical_span(outer), canonical_span(inner));
+  outer.0 <= inner.0 && inner.1 <= outer.1
+}
+
+/// Repositions a cached error's span after `delta` bytes were inserted (or removed, if negative) at `edit_at`.
+/// Indices before `edit_at` are kept, and the message is left untouched.
+pub fn shift_error(err: &ParseError, edit_at: usize, delta: isize) -> ParseError {
+  let shift = |idx: usize| {
+    if idx < edit_at {
+      idx
+    } else {
+      idx.saturating_add_signed(delta).max(edit_at)
+    }
+  };
+  ParseError::new((shift(err.span.0), shift(err.span.1)), err.message.clone())
+}
+
+/// Checks if `c` is a name char, as accepted by `parse_name`: an ASCII alphanumeric or one of `_.-/$`.
+pub fn is_name_char(c: char) -> bool {
+  c.is_ascii_alphanumeric() || "_.-/$".contains(c)
+}
+
+/// Shrinks the end of a span past any trailing whitespace and `//` comments.
+/// A `//` is only taken as a comment when it is followed by a newline inside the span,
+/// starts its line or follows whitespace, and isn't inside a `"`-quoted string on its line,
+/// so tokens like `https://a.com` are kept whole.
+/// Out-of-range ends are clamped to the input and moved back to the nearest char boundary.
+pub fn trim_trailing_trivia(span: (usize, usize), input: &str) -> (usize, usize) {
+  let floor = |mut idx: usize| {
+    while !input.is_char_boundary(idx) {
+      idx -= 1;
+    }
+    idx
+  };
+  let mut end = floor(span.1.min(input.len()));
+  let ini = floor(span.0.min(end));
+  loop {
+    let text = &input[ini..end];
+    let trimmed = text.trim_end_matches(|c: char| c.is_ascii_whitespace());
+    let ended_line = text[trimmed.len()..].contains('\n');
+    end = ini + trimmed.len();
+    let lin_ini = trimmed.rfind('\n').map_or(0, |i| i + 1);
+    match find_line_comment(&trimmed[lin_ini..]) {
+      Some(pos) if ended_line => end = ini + lin_ini + pos,
+      _ => break,
+    }
+  }
+  (ini, end)
+}
+
+/// Finds the first `//` in a line that isn't inside a `"`-quoted string.
+fn find_line_comment(line: &str) -> Option<usize> {
+  let mut quoted = false;
+  let mut chars = line.char_indices();
+  while let Some((i, chr)) = chars.next() {
+    match chr {
+      '\\' if quoted => { chars.next(); }
+      '"' => quoted = !quoted,
+      '/' if !quoted && line[i..].starts_with("//") && line[..i].chars().next_back().is_none_or(char::is_whitespace) => return Some(i),
+      _ => {}
+    }
+  }
+  None
+}
+
+/// Returns the visual width of `text`, expanding each tab to the next multiple of `tab_width`.
+fn visual_width(text: &str, tab_width: usize) -> usize {
+  let tab_width = tab_width.max(1);
+  text.chars().fold(0, |col, chr| {
+    if chr == '\t' { (col / tab_width + 1) * tab_width } else { col + 1 }
+  })
+}
+
+/// Maps byte indices of a source text to 1-based (line, column) pairs.
+/// Columns are counted in chars.
+pub struct LineMap<'i> {
+  source: &'i str,
+  starts: Vec<usize>,
+}
+
+impl<'i> LineMap<'i> {
+  pub fn new(source: &'i str) -> Self {
+    let mut starts = vec![0];
+    starts.extend(source.match_indices('\n').map(|(i, _)| i + 1));
+    LineMap { source, starts }
+  }
+
+  /// Returns the line and column of a byte index.
+  pub fn line_col(&self, index: usize) -> (usize, usize) {
+    let index = index.min(self.source.len());
+    let line = match self.starts.binary_search(&index) {
+      Ok(line) => line,
+      Err(line) => line - 1,
+    };
+    let col = self.source.get(self.starts[line]..index).map_or(0, |s| s.chars().count());
+    (line + 1, col + 1)
+  }
+}
+
+/// Removes ANSI escape sequences from a message.
+fn strip_ansi(text: &str) -> String {
+  let mut result = String::new();
+  let mut chars = text.chars();
+  while let Some(chr) = chars.next() {
+    if chr == '\x1b' {
+      for c in chars.by_ref() {
+        if c.is_ascii_alphabetic() {
+          break;
+        }
+      }
+    } else {
+      result.push(chr);
+    }
+  }
+  result
+}
+
+/// Resolves each error's start into a (line, column, message) triple, with ANSI codes stripped.
+pub fn resolve_errors(errors: &[ParseError], source: &str) -> Vec<(usize, usize, String)> {
+  let map = LineMap::new(source);
+  errors.iter().map(|err| {
+    let (line, col) = map.line_col(err.span.0);
+    (line, col, strip_ansi(&err.message))
+  }).collect()
+}
+
+/// Sorts errors by start position, keeping their relative order otherwise, and removes repeats with the same span and message.
+pub fn dedup_errors(mut errors: Vec<ParseError>) -> Vec<ParseError> {
+  errors.sort_by_key(|err| err.span.0);
+  let mut result: Vec<ParseError> = Vec::with_capacity(errors.len());
+  for err in errors {
+    let is_dup = result.iter().rev()
+      .take_while(|prev| prev.span.0 == err.span.0)
+      .any(|prev| prev.span == err.span && prev.message == err.message);
+    if !is_dup {
+      result.push(err);
+    }
+  }
+  result
+}
+
 pub trait Parser<'i> {
 
+  /// Error type returned by the parser methods. Set by `new_parser!`, defaulting to `ParseError`.
+  type Error: From<ParseError>;
+
   fn input(&mut self) -> &'i str;
   fn index(&mut self) -> &mut usize;
+  fn depth(&mut self) -> &mut usize;
+  fn max_depth(&mut self) -> &mut usize;
+  fn token_start(&mut self) -> &mut usize;
+  /// Byte span of the last run of trivia skipped by `skip_trivia`.
+  fn trivia(&mut self) -> &mut (usize, usize);
+  fn steps(&mut self) -> &mut usize;
+  fn budget(&mut self) -> &mut usize;
 
   /// Generates an error message for parsing failures, including the highlighted context.
-  fn expected<T>(&mut self, exp: &str) -> Result<T, ParseError> {
+  fn expected<T>(&mut self, exp: &str) -> Result<T, Self::Error> {
+    self.check_budget()?;
     let span = (*self.index(), *self.index() + 1);
     let ctx = highlight_error(span.0, span.1, self.input());
     let msg = format!("\x1b[1mPARSE_ERROR\n- expected: \x1b[0m{}\x1b[1m\n- detected:\n\x1b[0m{}", exp, ctx);
-    Err(ParseError::new(span, msg))
+    Err(ParseError::new(span, msg).into())
   }
 
   /// Generates an error message with an additional custom message.
-  fn expected_and<T>(&mut self, exp: &str, msg: &str) -> Result<T, ParseError> {
+  fn expected_and<T>(&mut self, exp: &str, msg: &str) -> Result<T, Self::Error> {
     let span = (*self.index(), *self.index() + 1);
+    self.expected_and_at(span, exp, msg)
+  }
+
+  /// Generates an error message with an additional custom message, highlighting the given span.
+  fn expected_and_at<T>(&mut self, span: (usize, usize), exp: &str, msg: &str) -> Result<T, Self::Error> {
+    self.check_budget()?;
     let ctx = highlight_error(span.0, span.1, self.input());
     let msg = format!("\x1b[1mPARSE_ERROR\n- information: \x1b[0m{}\x1b[1m\n- expected: \x1b[0m{}\x1b[1m\n- detected:\n\x1b[0m{}\x1b[1m\n ", msg, exp, ctx);
-    Err(ParseError::new(span, msg))
+    Err(ParseError::new(span, msg).into())
+  }
+
+  /// Checks if the parser tried to consume input after the step budget was used up.
+  fn budget_exceeded(&mut self) -> bool {
+    *self.steps() > *self.budget()
+  }
+
+  /// Counts `count` steps against the budget, returning how many of them fit.
+  /// If they don't all fit, the budget is marked as exceeded.
+  fn charge_steps(&mut self, count: usize) -> usize {
+    let left = self.budget().saturating_sub(*self.steps());
+    if count > left {
+      let budget = *self.budget();
+      *self.steps() = budget.saturating_add(1);
+      return left;
+    }
+    *self.steps() += count;
+    count
+  }
+
+  /// Errors with "parse budget exceeded" once the step budget is used up.
+  fn check_budget(&mut self) -> Result<(), Self::Error> {
+    if !self.budget_exceeded() {
+      return Ok(());
+    }
+    let span = (*self.index(), *self.index() + 1);
+    let ctx = highlight_error(span.0, span.1, self.input());
+    let msg = format!("\x1b[1mPARSE_ERROR\n- information: \x1b[0mparse budget exceeded (limit is {} steps)\x1b[1m\n- detected:\n\x1b[0m{}", *self.budget(), ctx);
+    Err(ParseError::new(span, msg).into())
   }
 
   /// Inspects the next character in the text without consuming it.
@@ -98,51 +459,398 @@ pub trait Parser<'i> {
     Some(&self.input()[*self.index()..end_index])
   }
 
-  /// Consumes the next character in the text.
+  /// Inspects the character right before the current index.
+  fn prev_char(&mut self) -> Option<char> {
+    self.input().get(..*self.index())?.chars().next_back()
+  }
+
+  /// Consumes the next character in the text, counting it against the step budget.
   fn advance_one(&mut self) -> Option<char> {
+    if *self.steps() >= *self.budget() {
+      let budget = *self.budget();
+      *self.steps() = budget.saturating_add(1);
+      return None;
+    }
     let chr = self.peek_one()?;
     *self.index() += chr.len_utf8();
+    *self.steps() += 1;
+    Some(chr)
+  }
+
+  /// Moves back by one character, returning it, or `None` if at the start.
+  fn unadvance_one(&mut self) -> Option<char> {
+    let chr = self.prev_char()?;
+    *self.index() -= chr.len_utf8();
     Some(chr)
   }
 
   /// Advances the parser by `count` characters, consuming them.
   fn advance_many(&mut self, count: usize) -> Option<&'i str> {
     let result = self.peek_many(count)?;
+    let chars = result.chars().count();
+    if self.charge_steps(chars) < chars {
+      return None;
+    }
     *self.index() += result.len();
     Some(result)
   }
 
-  /// Skips spaces in the text.
-  fn skip_spaces(&mut self) {
-    while let Some(c) = self.peek_one() {
-      if c.is_ascii_whitespace() {
-        self.advance_one();
-      } else {
-        break;
+  /// Checks if the cursor sits between a name char and a non-name char, like regex `\b`.
+  /// Name chars are the ones `is_name_char` accepts, and input edges count as non-name chars.
+  fn at_word_boundary(&mut self) -> bool {
+    let prev = self.prev_char();
+    let next = self.peek_one();
+    prev.is_some_and(is_name_char) != next.is_some_and(is_name_char)
+  }
+
+  /// Consumes the next character if it satisfies `pred`, returning it.
+  fn eat_if(&mut self, pred: impl Fn(char) -> bool) -> Option<char> {
+    match self.peek_one() {
+      Some(chr) if pred(chr) => self.advance_one(),
+      _ => None,
+    }
+  }
+
+  /// Consumes one char matching a class like `[a-zA-Z0-9_]`, returning it.
+  /// Supports ranges, single chars, `\`-escapes, and a leading `^` for negation.
+  fn parse_class_match(&mut self, class: &str) -> Result<char, Self::Error> {
+    let spec = class.strip_prefix('[').and_then(|c| c.strip_suffix(']')).unwrap_or(class);
+    let (negated, spec) = match spec.strip_prefix('^') {
+      Some(rest) => (true, rest),
+      None => (false, spec),
+    };
+    let mut ranges = Vec::new();
+    let mut chars = spec.chars().peekable();
+    while let Some(chr) = chars.next() {
+      let ini = if chr == '\\' { chars.next().unwrap_or('\\') } else { chr };
+      let mut end = ini;
+      if chars.peek() == Some(&'-') {
+        chars.next();
+        match chars.next() {
+          Some('\\') => end = chars.next().unwrap_or('\\'),
+          Some(chr) => end = chr,
+          None => ranges.push(('-', '-')),
+        }
       }
+      ranges.push((ini, end));
     }
+    match self.peek_one() {
+      Some(chr) if ranges.iter().any(|&(ini, end)| ini <= chr && chr <= end) != negated && self.advance_one().is_some() => Ok(chr),
+      _ => self.expected(class),
+    }
+  }
+
+  /// Consumes the next extended grapheme cluster in the text.
+  #[cfg(feature = "graphemes")]
+  fn advance_grapheme(&mut self) -> Option<&'i str> {
+    use unicode_segmentation::UnicodeSegmentation;
+    let grapheme = self.input().get(*self.index()..)?.graphemes(true).next()?;
+    let chars = grapheme.chars().count();
+    if self.charge_steps(chars) < chars {
+      return None;
+    }
+    *self.index() += grapheme.len();
+    Some(grapheme)
+  }
+
+  /// Skips spaces in the text.
+  fn skip_spaces(&mut self) {
+    self.take_while_ascii(|b| b.is_ascii_whitespace());
   }
 
-  /// Skips whitespace & comments in the text.
+  /// Skips whitespace & comments in the text, recording the skipped span in `trivia`.
   fn skip_trivia(&mut self) {
-    while let Some(c) = self.peek_one() {
-      if c.is_ascii_whitespace() {
-        self.advance_one();
-        continue;
-      }
-      if c == '/' && self.input().get(*self.index()..).unwrap_or_default().starts_with("//") {
-        while let Some(c) = self.peek_one() {
-          if c != '\n' {
-            self.advance_one();
-          } else {
-            break;
-          }
+    let ini_idx = *self.index();
+    loop {
+      self.skip_spaces();
+      let rest = self.input().get(*self.index()..).unwrap_or_default();
+      if rest.starts_with("//") {
+        // Skip the newline character as well
+        let len = rest.find('\n').map_or(rest.len(), |i| i + 1);
+        let mut fit = self.charge_steps(len);
+        while !rest.is_char_boundary(fit) {
+          fit -= 1;
+        }
+        *self.index() += fit;
+        if fit < len {
+          break;
         }
-        self.advance_one(); // Skip the newline character as well
         continue;
       }
       break;
     }
+    let end_idx = *self.index();
+    if end_idx > ini_idx {
+      // Extends the last run when contiguous, so consecutive skips record one span
+      let (prev_ini, prev_end) = *self.trivia();
+      *self.trivia() = (if prev_end == ini_idx { prev_ini } else { ini_idx }, end_idx);
+    }
+  }
+
+  /// Skips trivia like `skip_trivia`, returning how many `\n` characters were consumed.
+  fn skip_trivia_counting_newlines(&mut self) -> usize {
+    let ini_idx = *self.index();
+    self.skip_trivia();
+    self.input()[ini_idx..*self.index()].matches('\n').count()
+  }
+
+  /// Consumes a `//` comment at the cursor and its newline, returning the text after the `//`.
+  fn parse_line_comment(&mut self) -> Option<&'i str> {
+    if !self.starts_with("//") {
+      return None;
+    }
+    let ini_idx = *self.index();
+    self.advance_many(2)?;
+    let text = self.consume_line();
+    if self.budget_exceeded() {
+      *self.index() = ini_idx;
+      return None;
+    }
+    Some(text)
+  }
+
+  /// Consumes a `/* */` comment at the cursor, returning its inner text.
+  /// Returns `None`, consuming nothing, if the comment is missing or unterminated.
+  fn parse_block_comment(&mut self) -> Option<&'i str> {
+    self.parse_delimited_comment("/*", "*/", false)
+  }
+
+  /// Consumes a nestable `/* */` comment at the cursor, returning whether it is a `/** */` doc comment and its inner text.
+  /// Like in Rust, `/**/` and `/*** */` aren't doc comments. Returns `None`, consuming nothing, if missing or unterminated.
+  fn parse_block_comment_kind(&mut self) -> Option<(bool, &'i str)> {
+    let inner = self.parse_delimited_comment("/*", "*/", true)?;
+    match inner.strip_prefix('*') {
+      Some(doc) if !doc.is_empty() && !doc.starts_with('*') => Some((true, doc)),
+      _ => Some((false, inner)),
+    }
+  }
+
+  /// Consumes a comment between `open` and `close` at the cursor, like Haskell's `{- -}`, returning its inner text.
+  /// If `nested`, inner `open`s must be closed too. Returns `None`, consuming nothing, if missing or unterminated,
+  /// or if `open` or `close` is empty.
+  fn parse_delimited_comment(&mut self, open: &str, close: &str, nested: bool) -> Option<&'i str> {
+    if open.is_empty() || close.is_empty() || !self.starts_with(open) {
+      return None;
+    }
+    let ini_idx = *self.index() + open.len();
+    let bytes = self.input().as_bytes();
+    let mut end_idx = ini_idx;
+    let mut depth = 1;
+    loop {
+      let rest = bytes.get(end_idx..).filter(|rest| !rest.is_empty())?;
+      if nested && rest.starts_with(open.as_bytes()) {
+        depth += 1;
+        end_idx += open.len();
+      } else if rest.starts_with(close.as_bytes()) {
+        depth -= 1;
+        if depth == 0 {
+          break;
+        }
+        end_idx += close.len();
+      } else {
+        end_idx += 1;
+      }
+    }
+    let chars = self.input()[*self.index()..end_idx + close.len()].chars().count();
+    if self.charge_steps(chars) < chars {
+      return None;
+    }
+    *self.index() = end_idx + close.len();
+    Some(&self.input()[ini_idx..end_idx])
+  }
+
+  /// Skips whitespace & comments like `skip_trivia`, returning the text of each comment skipped.
+  fn skip_trivia_comments(&mut self) -> Vec<String> {
+    let mut comments = Vec::new();
+    loop {
+      self.skip_spaces();
+      match self.parse_line_comment() {
+        Some(text) => comments.push(text.to_owned()),
+        None => return comments,
+      }
+    }
+  }
+
+  /// Skips trivia like `skip_trivia_comments`, pushing each comment's text to `out` along with its placement.
+  /// A comment is `Trailing` if code precedes it on the same line, and `Leading` otherwise.
+  fn skip_trivia_classified(&mut self, out: &mut Vec<(CommentKind, String)>) {
+    loop {
+      self.skip_spaces();
+      let index = *self.index();
+      let lin_ini = self.input()[..index].rfind('\n').map_or(0, |i| i + 1);
+      let kind = if self.input()[lin_ini..index].trim().is_empty() { CommentKind::Leading } else { CommentKind::Trailing };
+      match self.parse_line_comment() {
+        Some(text) => out.push((kind, text.to_owned())),
+        None => return,
+      }
+    }
+  }
+
+  /// Skips whitespace, `//` and `/* */` comments, returning the byte span of each comment.
+  /// Line comment spans don't include the newline.
+  fn collect_comment_spans(&mut self) -> Vec<(usize, usize)> {
+    let mut spans = Vec::new();
+    loop {
+      self.skip_spaces();
+      let ini_idx = *self.index();
+      if let Some(text) = self.parse_line_comment() {
+        spans.push((ini_idx, ini_idx + 2 + text.len()));
+      } else if self.parse_block_comment().is_some() {
+        spans.push((ini_idx, *self.index()));
+      } else {
+        return spans;
+      }
+    }
+  }
+
+  /// Enters a nested construct, erroring if the nesting limit is exceeded.
+  /// Must be paired with a call to `leave`.
+  fn enter(&mut self) -> Result<(), Self::Error> {
+    if *self.depth() >= *self.max_depth() {
+      let msg = format!("nesting too deep (limit is {})", *self.max_depth());
+      return self.expected_and("shallower nesting", &msg);
+    }
+    *self.depth() += 1;
+    Ok(())
+  }
+
+  /// Leaves a nested construct entered with `enter`.
+  fn leave(&mut self) {
+    *self.depth() = self.depth().saturating_sub(1);
+  }
+
+  /// Returns the start index of the last token read by `parse_name`, `parse_u64` and friends.
+  fn last_token_start(&mut self) -> usize {
+    *self.token_start()
+  }
+
+  /// Marks the current index as the start of a token.
+  fn mark_token_start(&mut self) {
+    *self.token_start() = *self.index();
+  }
+
+  /// Returns the current position, including its line and column.
+  /// Note: this scans the input up to the cursor, so it is O(n).
+  fn position(&mut self) -> Position {
+    let byte = *self.index();
+    let before = &self.input()[..byte];
+    let lin_ini = before.rfind('\n').map_or(0, |i| i + 1);
+    let line = before.matches('\n').count() + 1;
+    let col = before[lin_ini..].chars().count() + 1;
+    Position { byte, line, col }
+  }
+
+  /// Returns the text of the line containing the cursor, without its terminator.
+  fn current_line(&mut self) -> &'i str {
+    let index = *self.index();
+    let input = self.input();
+    let lin_ini = input[..index].rfind('\n').map_or(0, |i| i + 1);
+    let lin_end = input[index..].find('\n').map_or(input.len(), |i| index + i);
+    let line = &input[lin_ini..lin_end];
+    line.strip_suffix('\r').unwrap_or(line)
+  }
+
+  /// Returns the 1-based column of the cursor within its line, counted in chars.
+  fn current_column(&mut self) -> usize {
+    self.position().col
+  }
+
+  /// Returns the visual width of the line before the cursor, expanding each tab to the next multiple of `tab_width`.
+  /// Unlike `current_column`, this is 0-based, so `"  \t"` with a tab width of 8 gives 8.
+  fn column_with_tabs(&mut self, tab_width: usize) -> usize {
+    let index = *self.index();
+    let before = &self.input()[..index];
+    let lin_ini = before.rfind('\n').map_or(0, |i| i + 1);
+    visual_width(&before[lin_ini..], tab_width)
+  }
+
+  /// At a line boundary, checks that the line just finished has at most `max` chars.
+  /// Returns an error spanning the overflowing part, or `None` if the line fits or the cursor isn't at a boundary.
+  fn check_line_length(&mut self, max: usize) -> Option<Self::Error> {
+    let index = *self.index();
+    let input = self.input();
+    let lin_end = if self.prev_char() == Some('\n') {
+      index - 1
+    } else if self.is_eof() || self.peek_one() == Some('\n') {
+      index
+    } else {
+      return None;
+    };
+    let lin_ini = input[..lin_end].rfind('\n').map_or(0, |i| i + 1);
+    let line = input[lin_ini..lin_end].strip_suffix('\r').unwrap_or(&input[lin_ini..lin_end]);
+    let (over_idx, _) = line.char_indices().nth(max)?;
+    let span = (lin_ini + over_idx, lin_ini + line.len());
+    let msg = format!("line is longer than {} characters", max);
+    self.expected_and_at::<()>(span, "shorter line", &msg).err()
+  }
+
+  /// Counts the characters from the cursor to the end of the input.
+  /// Note: this is O(n), so cache the result if it is needed often.
+  fn chars_left(&mut self) -> usize {
+    self.input().get(*self.index()..).map_or(0, |s| s.chars().count())
+  }
+
+  /// Returns the byte offsets, relative to the current index, of every remaining char matching `pred`.
+  /// Doesn't consume input. Note: this scans the whole rest of the input, so it is O(n).
+  fn positions(&mut self, pred: impl Fn(char) -> bool) -> Vec<usize> {
+    let rest = self.input().get(*self.index()..).unwrap_or_default();
+    rest.char_indices().filter(|(_, c)| pred(*c)).map(|(i, _)| i).collect()
+  }
+
+  /// Returns the byte length of the next token after trivia, without consuming anything.
+  /// A token is a run of name chars (including non-ASCII alphanumerics), a quoted string, or a single char.
+  fn peek_token_len(&mut self) -> usize {
+    let old_idx = *self.index();
+    self.skip_trivia();
+    let ini_idx = *self.index();
+    if let Some(quote @ ('"' | '\'')) = self.peek_one() {
+      if self.skip_string(quote).is_err() {
+        *self.index() = self.input().len();
+      }
+    } else if self.take_while(|c| is_name_char(c) || c.is_alphanumeric()).is_empty() {
+      self.advance_one();
+    }
+    let len = *self.index() - ini_idx;
+    *self.index() = old_idx;
+    len
+  }
+
+  /// Skips trivia and the next token, as measured by `peek_token_len`, for error recovery.
+  /// Always makes progress unless at the end of the input. If the cursor is inside the token starting at
+  /// `last_token_start`, the rest of that token is skipped instead, so after `parse_u64` reads `12` from `12x3`,
+  /// the whole `12x3` is consumed.
+  fn skip_token(&mut self) {
+    let old_idx = *self.index();
+    let tok_ini = self.last_token_start();
+    if tok_ini < old_idx {
+      *self.index() = tok_ini;
+      let end_idx = tok_ini + self.peek_token_len();
+      if end_idx > old_idx {
+        *self.index() = end_idx;
+        return;
+      }
+      *self.index() = old_idx;
+    }
+    self.skip_trivia();
+    let ini_idx = *self.index();
+    let end_idx = ini_idx + self.peek_token_len();
+    *self.index() = end_idx;
+  }
+
+  /// Describes the parser state, like `index=5 next='λ' rest="x(x)"`, for debugging.
+  /// The rest of the input is truncated to 16 chars.
+  fn debug_state(&mut self) -> String {
+    let index = *self.index();
+    let mut chars = self.input().get(index..).unwrap_or_default().chars();
+    match chars.next() {
+      None => format!("index={} next=EOF", index),
+      Some(next) => {
+        let rest = chars.as_str();
+        let head = rest.chars().take(16).collect::<String>();
+        let dots = if head.len() < rest.len() { "..." } else { "" };
+        format!("index={} next={:?} rest={:?}{}", index, next, head, dots)
+      }
+    }
   }
 
   /// Checks if the parser has reached the end of the input.
@@ -151,28 +859,53 @@ pub trait Parser<'i> {
   }
 
   /// Consumes an instance of the given string, erroring if it is not found.
-  fn consume(&mut self, text: &str) -> Result<(), ParseError> {
+  fn consume(&mut self, text: &str) -> Result<(), Self::Error> {
     self.skip_trivia();
     if self.input().get(*self.index()..).unwrap_or_default().starts_with(text) {
+      let chars = text.chars().count();
+      if self.charge_steps(chars) == chars {
+        *self.index() += text.len();
+        return Ok(());
+      }
+    }
+    self.expected(text)
+  }
+
+  /// Consumes as many consecutive copies of `text` as present, returning the count.
+  /// Doesn't skip trivia.
+  fn consume_repeated(&mut self, text: &str) -> usize {
+    let mut count = 0;
+    let chars = text.chars().count();
+    while !text.is_empty() && self.input().get(*self.index()..).unwrap_or_default().starts_with(text) {
+      if self.charge_steps(chars) < chars {
+        break;
+      }
       *self.index() += text.len();
-      Ok(())
-    } else {
-      self.expected(text)
+      count += 1;
+    }
+    count
+  }
+
+  /// Consumes the first of `options` found after trivia, returning its index in `options`.
+  fn consume_one_of(&mut self, options: &[&str]) -> Result<usize, Self::Error> {
+    self.skip_trivia();
+    let rest = self.input().get(*self.index()..).unwrap_or_default();
+    match options.iter().position(|opt| rest.starts_with(opt)) {
+      Some(i) if self.advance_many(options[i].chars().count()).is_some() => Ok(i),
+      _ => self.expected(&options.join(" | ")),
     }
   }
 
   /// Checks if the next characters in the input start with the given string.
   fn starts_with(&mut self, text: &str) -> bool {
-    self.peek_many(text.chars().count()).map_or(false, |s| s == text)
+    self.peek_many(text.chars().count()) == Some(text)
   }
 
   /// Consumes all contiguous characters matching a given predicate.
   fn take_while(&mut self, mut f: impl FnMut(char) -> bool) -> &'i str {
     let start = *self.index();
     while let Some(c) = self.peek_one() {
-      if f(c) {
-        self.advance_one();
-      } else {
+      if !f(c) || self.advance_one().is_none() {
         break;
       }
     }
@@ -180,85 +913,2561 @@ pub trait Parser<'i> {
     &self.input()[start..end]
   }
 
-  /// Parses a name from the input, supporting alphanumeric characters, underscores, periods, and hyphens.
-  fn parse_name(&mut self) -> Result<String, ParseError> {
-    self.skip_trivia();
-    let name = self.take_while(|c| c.is_ascii_alphanumeric() || "_.-/$".contains(c));
-    if name.is_empty() {
-      self.expected("name")
-    } else {
-      Ok(name.to_owned())
+  /// Consumes all contiguous ASCII characters matching a given byte predicate.
+  /// Faster than `take_while`, as it scans bytes directly. Stops at the first non-ASCII byte.
+  /// Each byte consumed counts as a step against the budget, and the scan stops once it is used up.
+  fn take_while_ascii(&mut self, pred: impl Fn(u8) -> bool) -> &'i str {
+    let ini_idx = *self.index();
+    let bytes = self.input().as_bytes().get(ini_idx..).unwrap_or_default();
+    let len = bytes.iter().position(|&b| !b.is_ascii() || !pred(b)).unwrap_or(bytes.len());
+    let len = self.charge_steps(len);
+    *self.index() += len;
+    &self.input()[ini_idx..ini_idx + len]
+  }
+
+  /// Consumes a single line terminator: `\n`, `\r\n`, or a lone `\r`.
+  fn consume_newline(&mut self) -> Result<(), Self::Error> {
+    match self.peek_one() {
+      Some('\n') => { self.advance_one(); },
+      Some('\r') => { self.advance_one(); self.eat_if(|c| c == '\n'); },
+      _ => return self.expected("newline"),
     }
+    self.check_budget()
   }
 
-  /// Parses a u64 from the input, supporting dec, hex (0xNUM), and bin (0bNUM).
-  fn parse_u64(&mut self) -> Result<u64, ParseError> {
+  /// Consumes the rest of the current line, including its `\n` or `\r\n` terminator.
+  /// Returns the line's text without the terminator, or the remaining text at EOF.
+  fn consume_line(&mut self) -> &'i str {
+    let line = self.take_while(|c| c != '\n');
+    self.advance_one();
+    line.strip_suffix('\r').unwrap_or(line)
+  }
+
+  /// Parses the raw lines after an opening `fence` line, up to a line consisting solely of `fence`, like `---` front matter.
+  /// The cursor may be before or after the opening line's terminator. The returned text excludes the terminator
+  /// before the closing fence, and the closing fence's own terminator is consumed.
+  fn parse_fenced_block(&mut self, fence: &str) -> Result<&'i str, Self::Error> {
+    if matches!(self.peek_one(), Some('\n' | '\r')) {
+      self.consume_newline()?;
+    }
+    let ini_idx = *self.index();
+    loop {
+      self.check_budget()?;
+      if self.is_eof() {
+        return self.expected(fence);
+      }
+      let lin_ini = *self.index();
+      if self.consume_line() == fence {
+        let body = &self.input()[ini_idx..lin_ini];
+        let body = body.strip_suffix('\n').unwrap_or(body);
+        return Ok(body.strip_suffix('\r').unwrap_or(body));
+      }
+    }
+  }
+
+  /// Runs `f`, returning its value along with the byte span it consumed.
+  /// The span starts after leading trivia and ends before any trailing trivia `f` skipped with `skip_trivia`.
+  fn with_span<T>(&mut self, f: impl FnOnce(&mut Self) -> Result<T, Self::Error>) -> Result<(T, (usize, usize)), Self::Error> {
     self.skip_trivia();
-    let radix = match self.peek_many(2) {
-      Some("0x") => { self.advance_many(2); 16 },
-      Some("0b") => { self.advance_many(2); 2 },
-      _ => { 10 },
-    };
-    let num_str = self.take_while(move |c| c.is_digit(radix) || c == '_');
-    let num_str = num_str.chars().filter(|c| *c != '_').collect::<String>();
-    if num_str.is_empty() {
-      self.expected("numeric digit")
+    let ini_idx = *self.index();
+    let val = f(self)?;
+    let end_idx = *self.index();
+    let (triv_ini, triv_end) = *self.trivia();
+    let end_idx = if triv_end == end_idx { triv_ini.clamp(ini_idx, end_idx) } else { end_idx };
+    Ok((val, (ini_idx, end_idx)))
+  }
+
+  /// Runs `f`, discarding its value and returning the source text it consumed, like `with_span`.
+  fn recognize<T>(&mut self, f: impl FnOnce(&mut Self) -> Result<T, Self::Error>) -> Result<&'i str, Self::Error> {
+    let (_, (ini_idx, end_idx)) = self.with_span(f)?;
+    Ok(&self.input()[ini_idx..end_idx])
+  }
+
+  /// Runs `f` and ensures it consumed the whole input, up to trailing trivia.
+  fn parse_all<T>(&mut self, f: impl FnOnce(&mut Self) -> Result<T, Self::Error>) -> Result<T, Self::Error> {
+    let val = f(self)?;
+    self.skip_trivia();
+    self.check_budget()?;
+    if self.is_eof() {
+      Ok(val)
     } else {
-      u64::from_str_radix(&num_str, radix)
-        .map_err(|e| self.expected_and::<u64>("integer", &e.to_string()).unwrap_err())
+      self.expected("end of input")
     }
   }
 
-  /// Parses a single unicode character, supporting scape sequences.
-  fn parse_char(&mut self) -> Result<char, ParseError> {
-    match self.advance_one() {
-      Some('\\') => match self.advance_one() {
-        Some('u') => {
-          self.consume("{")?;
-          let codepoint_str = self.take_while(|c| c.is_digit(16));
-          self.consume("}")?;
-          u32::from_str_radix(codepoint_str, 16)
-            .ok().and_then(std::char::from_u32)
-            .ok_or_else(|| self.expected::<char>("unicode-codepoint").unwrap_err())
+  /// Parses atoms joined by binary operators from `ops`, given as `(op, precedence, associativity)`, folding them with `combine`.
+  /// Higher precedences bind tighter. When several operators match, the longest one wins.
+  fn parse_binops<T>(&mut self, mut atom: impl FnMut(&mut Self) -> Result<T, Self::Error>, ops: &[(&str, u8, Assoc)], combine: impl Fn(T, &str, T) -> T) -> Result<T, Self::Error> {
+    #[allow(clippy::type_complexity)]
+    fn climb<'i, P: Parser<'i> + ?Sized, T>(p: &mut P, min_prec: u16, atom: &mut dyn FnMut(&mut P) -> Result<T, P::Error>, ops: &[(&str, u8, Assoc)], combine: &dyn Fn(T, &str, T) -> T) -> Result<T, P::Error> {
+      let mut lhs = atom(p)?;
+      loop {
+        let ini_idx = *p.index();
+        p.skip_trivia();
+        let rest = p.input().get(*p.index()..).unwrap_or_default();
+        let op = ops.iter().filter(|(op, _, _)| rest.starts_with(op)).max_by_key(|(op, _, _)| op.len());
+        match op {
+          Some(&(op, prec, assoc)) if prec as u16 >= min_prec => {
+            p.advance_many(op.chars().count());
+            p.check_budget()?;
+            let next_prec = if assoc == Assoc::Left { prec as u16 + 1 } else { prec as u16 };
+            let rhs = climb(p, next_prec, atom, ops, combine)?;
+            lhs = combine(lhs, op, rhs);
+          }
+          _ => {
+            *p.index() = ini_idx;
+            return Ok(lhs);
+          }
         }
-        Some('0') => Ok('\0'),
-        Some('n') => Ok('\n'),
-        Some('r') => Ok('\r'),
-        Some('t') => Ok('\t'),
-        Some('\'') => Ok('\''),
-        Some('\"') => Ok('\"'),
-        Some('\\') => Ok('\\'),
-        Some(chr) => self.expected(&format!("\\{}", chr)),
-        None => self.expected("escaped-char"),
-      },
-      Some(other) => Ok(other),
-      None => self.expected("char"),
+      }
+    }
+    climb(self, 0, &mut atom, ops, &combine)
+  }
+
+  /// Parses `atom` surrounded by any number of prefix and postfix operators, like `!-x` or `x!`.
+  /// Postfix operators bind tighter, and prefix operators apply outward-in, so `!-x` is `!(-x)`.
+  /// When several operators match, the longest one wins.
+  #[allow(clippy::type_complexity)]
+  fn parse_unary<T>(&mut self, prefix_ops: &[(&str, fn(T) -> T)], postfix_ops: &[(&str, fn(T) -> T)], mut atom: impl FnMut(&mut Self) -> Result<T, Self::Error>) -> Result<T, Self::Error> {
+    let find_op = |p: &mut Self, ops: &[(&str, fn(T) -> T)]| {
+      let rest = p.input().get(*p.index()..).unwrap_or_default();
+      let (op, f) = ops.iter().filter(|(op, _)| rest.starts_with(op)).max_by_key(|(op, _)| op.len())?;
+      p.advance_many(op.chars().count())?;
+      Some(*f)
+    };
+    let mut prefixes = Vec::new();
+    loop {
+      self.skip_trivia();
+      match find_op(self, prefix_ops) {
+        Some(f) => prefixes.push(f),
+        None => break,
+      }
+    }
+    let mut val = atom(self)?;
+    loop {
+      let ini_idx = *self.index();
+      self.skip_trivia();
+      match find_op(self, postfix_ops) {
+        Some(f) => val = f(val),
+        None => { *self.index() = ini_idx; break; },
+      }
     }
+    self.check_budget()?;
+    Ok(prefixes.into_iter().rev().fold(val, |val, f| f(val)))
   }
 
-  /// Parses a quoted character, like 'x'.
-  fn parse_quoted_char(&mut self) -> Result<char, ParseError> {
+  /// Parses a `: type` annotation with `ty` if the next token is `:`, like in `x: Nat`.
+  /// Returns `None`, consuming nothing, otherwise. A `::` isn't taken as an annotation.
+  fn parse_optional_annotation<T>(&mut self, ty: impl FnOnce(&mut Self) -> Result<T, Self::Error>) -> Result<Option<T>, Self::Error> {
+    let ini_idx = *self.index();
     self.skip_trivia();
-    self.consume("'")?;
-    let chr = self.parse_char()?;
-    self.consume("'")?;
-    Ok(chr)
+    if !self.starts_with(":") || self.starts_with("::") {
+      *self.index() = ini_idx;
+      return Ok(None);
+    }
+    self.consume(":")?;
+    Ok(Some(ty(self)?))
   }
 
-  /// Parses a quoted string, like "foobar".
-  fn parse_quoted_string(&mut self) -> Result<String, ParseError> {
+  /// Parses one or more items separated by `sep`, like `a, b, c`, stopping when no `sep` follows.
+  /// Trivia after the last item is left unconsumed.
+  fn parse_sep_by1<T>(&mut self, mut item: impl FnMut(&mut Self) -> Result<T, Self::Error>, sep: &str) -> Result<Vec<T>, Self::Error> {
+    let mut items = vec![item(self)?];
+    loop {
+      let ini_idx = *self.index();
+      self.skip_trivia();
+      if !self.starts_with(sep) {
+        *self.index() = ini_idx;
+        return Ok(items);
+      }
+      self.consume(sep)?;
+      items.push(item(self)?);
+    }
+  }
+
+  /// Parses items up to and including `close`, separated by commas or newlines, like `a, b` and `c` on the next line.
+  /// Redundant separators, like blank lines or a trailing comma, are ignored. The opening delimiter must already be consumed.
+  fn parse_items_sep_comma_or_newline<T>(&mut self, mut item: impl FnMut(&mut Self) -> Result<T, Self::Error>, close: &str) -> Result<Vec<T>, Self::Error> {
+    let mut items = Vec::new();
+    loop {
+      let mut has_sep = false;
+      loop {
+        has_sep |= self.skip_trivia_counting_newlines() > 0;
+        if self.eat_if(|c| c == ',').is_none() {
+          break;
+        }
+        has_sep = true;
+      }
+      if self.starts_with(close) {
+        self.consume(close)?;
+        return Ok(items);
+      }
+      if self.is_eof() {
+        return self.expected(close);
+      }
+      if !items.is_empty() && !has_sep {
+        return self.expected("',' or newline");
+      }
+      items.push(item(self)?);
+    }
+  }
+
+  /// Parses exactly `n` items between `open` and `close`, separated by `sep`.
+  /// Errors at the offending position if there are too few or too many items.
+  fn parse_fixed<T>(&mut self, n: usize, open: &str, sep: &str, close: &str, mut item: impl FnMut(&mut Self) -> Result<T, Self::Error>) -> Result<Vec<T>, Self::Error> {
+    self.consume(open)?;
+    let mut items = Vec::with_capacity(n);
+    for i in 0..n {
+      self.skip_trivia();
+      if self.starts_with(close) {
+        return self.expected_and(if i == 0 { "item" } else { sep }, &format!("expected {} items, found {}", n, i));
+      }
+      if i > 0 {
+        self.consume(sep)?;
+      }
+      items.push(item(self)?);
+    }
     self.skip_trivia();
-    self.consume("\"")?;
+    if self.starts_with(sep) {
+      return self.expected_and(close, &format!("expected {} items, found more", n));
+    }
+    self.consume(close)?;
+    Ok(items)
+  }
+
+  /// Splits the rest of the input into off-side rule tokens, like Python's `INDENT`/`DEDENT`, consuming it all.
+  /// Indentation is measured with tabs expanded to multiples of `tab_width`, and blank lines are skipped.
+  /// Errors on a dedent that doesn't return to an enclosing indentation level.
+  fn layout_tokens(&mut self, tab_width: usize) -> Result<Vec<LayoutToken<'i>>, Self::Error> {
+    let mut tokens = Vec::new();
+    let mut levels = vec![0];
+    while !self.is_eof() {
+      let lin_ini = *self.index();
+      let line = self.consume_line();
+      self.check_budget()?;
+      let content = line.trim_start_matches([' ', '\t']);
+      if content.trim().is_empty() {
+        continue;
+      }
+      let width = visual_width(&line[..line.len() - content.len()], tab_width);
+      if width > *levels.last().unwrap() {
+        levels.push(width);
+        tokens.push(LayoutToken::Indent);
+      }
+      while width < *levels.last().unwrap() {
+        levels.pop();
+        tokens.push(LayoutToken::Dedent);
+      }
+      if width != *levels.last().unwrap() {
+        *self.index() = lin_ini + line.len() - content.len();
+        return self.expected_and("indentation", &format!("dedent to column {} does not match any outer indentation level", width));
+      }
+      tokens.push(LayoutToken::Content(content));
+      tokens.push(LayoutToken::Newline);
+    }
+    tokens.extend(levels[1..].iter().map(|_| LayoutToken::Dedent));
+    Ok(tokens)
+  }
+
+  /// Parses `line` repeatedly while the next non-empty line is indented by more than `base_col`.
+  /// Each item must start on a new line. Stops before the first dedented line.
+  fn parse_indented_block<T>(&mut self, base_col: usize, mut line: impl FnMut(&mut Self) -> Result<T, Self::Error>) -> Result<Vec<T>, Self::Error> {
+    let mut items = Vec::new();
+    loop {
+      let ini_idx = *self.index();
+      self.skip_spaces();
+      let idx = *self.index();
+      let lin_ini = self.input()[..idx].rfind('\n').map_or(0, |i| i + 1);
+      let indent = self.input()[lin_ini..idx].chars().count();
+      if self.is_eof() || lin_ini < ini_idx || indent <= base_col {
+        *self.index() = ini_idx;
+        self.check_budget()?;
+        return Ok(items);
+      }
+      items.push(line(self)?);
+    }
+  }
+
+  /// Parses a `sep`-separated list between `open` and `close`, pairing each item with the comments before it.
+  /// A trailing separator is allowed. Comments right before `close` are discarded.
+  fn parse_list_with_comments<T>(&mut self, open: &str, sep: &str, close: &str, mut item: impl FnMut(&mut Self) -> Result<T, Self::Error>) -> Result<Vec<(T, Vec<String>)>, Self::Error> {
+    self.consume(open)?;
+    let mut items = Vec::new();
+    loop {
+      let mut comments = self.skip_trivia_comments();
+      if self.starts_with(close) {
+        break;
+      }
+      if !items.is_empty() {
+        self.consume(sep)?;
+        comments.extend(self.skip_trivia_comments());
+        if self.starts_with(close) {
+          break;
+        }
+      }
+      items.push((item(self)?, comments));
+    }
+    self.consume(close)?;
+    Ok(items)
+  }
+
+  /// Parses a `<name>...</name>` tag pair, calling `inner` with the tag name to parse the contents.
+  /// Errors if the closing tag's name differs, naming both tags.
+  fn parse_tagged<T>(&mut self, mut inner: impl FnMut(&mut Self, &str) -> Result<T, Self::Error>) -> Result<T, Self::Error> {
+    self.consume("<")?;
+    let name = self.parse_name()?;
+    self.consume(">")?;
+    let val = inner(self, &name)?;
+    self.consume("</")?;
+    let (close, span) = self.parse_name_spanned()?;
+    if close != name {
+      let msg = format!("closing tag </{}> does not match opening tag <{}>", close, name);
+      return self.expected_and_at(span, &format!("</{}>", name), &msg);
+    }
+    self.consume(">")?;
+    Ok(val)
+  }
+
+  /// Parses a `{ key: value, ... }` object, returning its entries in order.
+  /// Keys are names or quoted strings. Errors on duplicate keys, pointing at the second one.
+  fn parse_object<V>(&mut self, mut value: impl FnMut(&mut Self) -> Result<V, Self::Error>) -> Result<Vec<(String, V)>, Self::Error> {
+    self.consume("{")?;
+    let mut entries: Vec<(String, V)> = Vec::new();
+    loop {
+      self.skip_trivia();
+      if self.starts_with("}") {
+        break;
+      }
+      if !entries.is_empty() {
+        self.consume(",")?;
+        self.skip_trivia();
+        if self.starts_with("}") {
+          break;
+        }
+      }
+      let key_idx = *self.index();
+      let key = if self.starts_with("\"") { self.parse_quoted_string()? } else { self.parse_name()? };
+      if entries.iter().any(|(k, _)| *k == key) {
+        *self.index() = key_idx;
+        return self.expected_and("unique key", &format!("duplicate key '{}'", key));
+      }
+      self.consume(":")?;
+      let val = value(self)?;
+      entries.push((key, val));
+    }
+    self.consume("}")?;
+    Ok(entries)
+  }
+
+  /// Parses `(a)` as a parenthesized item, and `()`, `(a,)` or `(a, b)` as a tuple.
+  fn parse_tuple_or_paren<T>(&mut self, mut item: impl FnMut(&mut Self) -> Result<T, Self::Error>) -> Result<TupleOrParen<T>, Self::Error> {
+    self.consume("(")?;
+    let mut items = Vec::new();
+    let mut trailing = false;
+    loop {
+      self.skip_trivia();
+      if self.starts_with(")") {
+        break;
+      }
+      items.push(item(self)?);
+      self.skip_trivia();
+      trailing = self.starts_with(",");
+      if !trailing {
+        break;
+      }
+      self.consume(",")?;
+    }
+    self.consume(")")?;
+    if items.len() == 1 && !trailing {
+      Ok(TupleOrParen::Paren(items.pop().unwrap()))
+    } else {
+      Ok(TupleOrParen::Tuple(items))
+    }
+  }
+
+  /// Parses HTML-like attributes, like `a="1" b='2' c`, until a `>` or `/>`, which isn't consumed.
+  /// Values are taken raw, without escapes. Attributes without a value get an empty string.
+  fn parse_attributes(&mut self) -> Result<Vec<(String, String)>, Self::Error> {
+    let mut attrs = Vec::new();
+    loop {
+      self.skip_trivia();
+      self.check_budget()?;
+      if self.is_eof() || self.starts_with(">") || self.starts_with("/>") {
+        return Ok(attrs);
+      }
+      let name = self.take_while_ascii(|b| b.is_ascii_alphanumeric() || b"_-:.".contains(&b));
+      if name.is_empty() {
+        return self.expected("attribute name");
+      }
+      self.skip_trivia();
+      let mut value = String::new();
+      if self.eat_if(|c| c == '=').is_some() {
+        self.skip_trivia();
+        let quote = match self.peek_one() {
+          Some(quote @ ('"' | '\'')) => quote,
+          _ => return self.expected("quoted value"),
+        };
+        self.advance_one();
+        value.push_str(self.take_while(|c| c != quote));
+        if self.advance_one().is_none() {
+          return self.expected(&quote.to_string());
+        }
+      }
+      attrs.push((name.to_owned(), value));
+    }
+  }
+
+  /// Runs each parser from the same position and keeps the one that consumed the most input.
+  /// Ties go to the earliest parser. If all fail, returns the error of the one that got the furthest.
+  #[allow(clippy::type_complexity)]
+  fn longest<T>(&mut self, parsers: &mut [&mut dyn FnMut(&mut Self) -> Result<T, Self::Error>]) -> Result<T, Self::Error> {
+    let ini_idx = *self.index();
+    let mut best: Option<(usize, T)> = None;
+    let mut error: Option<(usize, Self::Error)> = None;
+    for parser in parsers.iter_mut() {
+      *self.index() = ini_idx;
+      match parser(self) {
+        Ok(val) => {
+          let end_idx = *self.index();
+          let better = match &best {
+            Some((idx, _)) => end_idx > *idx,
+            None => true,
+          };
+          if better {
+            best = Some((end_idx, val));
+          }
+        }
+        Err(err) => {
+          let end_idx = *self.index();
+          let further = match &error {
+            Some((idx, _)) => end_idx > *idx,
+            None => true,
+          };
+          if further {
+            error = Some((end_idx, err));
+          }
+        }
+      }
+    }
+    match best {
+      Some((end_idx, val)) => {
+        *self.index() = end_idx;
+        Ok(val)
+      }
+      None => {
+        *self.index() = ini_idx;
+        match error {
+          Some((_, err)) => Err(err),
+          None => self.expected("alternative"),
+        }
+      }
+    }
+  }
+
+  /// Consumes the prefix matching a tiny regex-like pattern, returning it.
+  /// Supports literal chars, `.`, `\d`, `\w`, `\s`, and the `+`/`*` quantifiers.
+  /// Quantifiers are greedy and never backtrack.
+  fn match_pattern(&mut self, pattern: &str) -> Result<&'i str, Self::Error> {
+    let ini_idx = *self.index();
+    let mut pat = pattern.chars().peekable();
+    while let Some(tok) = pat.next() {
+      let class = if tok == '\\' { pat.next().map(|c| ('\\', c)) } else { Some((tok, tok)) };
+      let matches = |chr: char| match class {
+        Some(('\\', 'd')) => chr.is_ascii_digit(),
+        Some(('\\', 'w')) => chr.is_alphanumeric() || chr == '_',
+        Some(('\\', 's')) => chr.is_whitespace(),
+        Some(('\\', lit)) => chr == lit,
+        Some(('.', _)) => true,
+        Some((lit, _)) => chr == lit,
+        None => false,
+      };
+      let (min, max) = match pat.peek() {
+        Some('+') => { pat.next(); (1, usize::MAX) },
+        Some('*') => { pat.next(); (0, usize::MAX) },
+        _ => (1, 1),
+      };
+      let mut count = 0;
+      while count < max {
+        match self.peek_one() {
+          Some(chr) if matches(chr) && self.advance_one().is_some() => count += 1,
+          _ => break,
+        }
+      }
+      if count < min {
+        *self.index() = ini_idx;
+        return self.expected(pattern);
+      }
+    }
+    self.check_budget()?;
+    let end_idx = *self.index();
+    Ok(&self.input()[ini_idx..end_idx])
+  }
+
+  /// Parses a name from the input, supporting alphanumeric characters, underscores, periods, and hyphens.
+  fn parse_name(&mut self) -> Result<String, Self::Error> {
+    self.skip_trivia();
+    self.mark_token_start();
+    let name = self.take_while_ascii(|b| is_name_char(b as char));
+    self.check_budget()?;
+    if name.is_empty() {
+      self.expected("name")
+    } else {
+      Ok(name.to_owned())
+    }
+  }
+
+  /// Parses a name like `parse_name`, also decoding `\u{...}` escapes, like `fo\u{6F}` for `foo`.
+  /// Escaped chars must be name chars or non-ASCII alphanumerics.
+  fn parse_name_with_escapes(&mut self) -> Result<String, Self::Error> {
+    self.skip_trivia();
+    self.mark_token_start();
+    let mut name = String::new();
+    loop {
+      name.push_str(self.take_while_ascii(|b| is_name_char(b as char)));
+      if !self.starts_with("\\") {
+        break;
+      }
+      let esc_idx = *self.index();
+      if !self.starts_with("\\u") {
+        *self.index() += 1;
+        return self.expected("u");
+      }
+      let chr = self.parse_char()?;
+      if !(is_name_char(chr) || chr.is_alphanumeric()) {
+        *self.index() = esc_idx;
+        return self.expected_and("name", &format!("{:?} is not a valid name character", chr));
+      }
+      name.push(chr);
+    }
+    self.check_budget()?;
+    if name.is_empty() {
+      self.expected("name")
+    } else {
+      Ok(name)
+    }
+  }
+
+  /// Parses a backtick-quoted identifier, like `col name`, where a doubled backtick stands for a literal one.
+  fn parse_backtick_ident(&mut self) -> Result<String, Self::Error> {
+    self.parse_quoted_ident('`')
+  }
+
+  /// Parses an identifier wrapped in `quote`, where a doubled quote stands for a literal one.
+  /// Backslashes are taken literally.
+  fn parse_quoted_ident(&mut self, quote: char) -> Result<String, Self::Error> {
+    self.skip_trivia();
+    self.mark_token_start();
+    self.consume(&quote.to_string())?;
     let mut result = String::new();
+    loop {
+      match self.advance_one() {
+        Some(chr) if chr == quote && self.peek_one() == Some(quote) => {
+          self.advance_one();
+          result.push(quote);
+        }
+        Some(chr) if chr == quote => break,
+        Some(chr) => result.push(chr),
+        None => return self.expected(&quote.to_string()),
+      }
+    }
+    if result.is_empty() {
+      *self.index() -= quote.len_utf8();
+      return self.expected("identifier");
+    }
+    Ok(result)
+  }
+
+  /// Parses a SQL-style identifier, either bare or quoted with `"` or `` ` ``.
+  /// Returns the name and whether it was quoted, since quoted names are case-sensitive.
+  fn parse_ident_maybe_quoted(&mut self) -> Result<(String, bool), Self::Error> {
+    self.skip_trivia();
+    match self.peek_one() {
+      Some(quote @ ('"' | '`')) => Ok((self.parse_quoted_ident(quote)?, true)),
+      _ => Ok((self.parse_name()?, false)),
+    }
+  }
+
+  /// Returns the index of `name` in the keyword table `kws`, if it is a keyword.
+  /// Meant to be used after `parse_name`, instead of trying `consume` per keyword.
+  /// `kws` must be sorted, as it is binary searched; the result is unspecified otherwise.
+  fn classify_keyword(&self, name: &str, kws: &[&str]) -> Option<usize> {
+    debug_assert!(kws.is_sorted(), "keyword table must be sorted");
+    kws.binary_search(&name).ok()
+  }
+
+  /// Parses a name and returns the value of the matching `(keyword, value)` pair.
+  /// Errors listing the valid keywords if the name doesn't match one of them exactly.
+  fn parse_enum<T: Copy>(&mut self, variants: &[(&str, T)]) -> Result<T, Self::Error> {
+    self.skip_trivia();
+    let ini_idx = *self.index();
+    let name = self.parse_name().ok();
+    match variants.iter().find(|(kw, _)| Some(*kw) == name.as_deref()) {
+      Some((_, val)) => Ok(*val),
+      None => {
+        *self.index() = ini_idx;
+        let opts = variants.iter().map(|(kw, _)| *kw).collect::<Vec<_>>().join(" | ");
+        self.expected(&opts)
+      }
+    }
+  }
+
+  /// Parses a `::`-separated name like `std::collections::HashMap`, returning its segments.
+  /// Errors on a trailing `::` with no segment after it.
+  fn parse_qualified_name(&mut self) -> Result<Vec<String>, Self::Error> {
+    let name = self.parse_name()?;
+    let mut segments = vec![name];
+    while self.starts_with("::") {
+      self.advance_many(2);
+      let segment = self.take_while_ascii(|b| is_name_char(b as char));
+      if segment.is_empty() {
+        return self.expected_and("name", "expected a segment after '::'");
+      }
+      segments.push(segment.to_owned());
+    }
+    self.check_budget()?;
+    Ok(segments)
+  }
+
+  /// Parses a `/`-separated path like `a/b/c`, returning its segments.
+  /// Errors on empty and `..` segments, pointing at the bad segment.
+  fn parse_path(&mut self) -> Result<Vec<String>, Self::Error> {
+    let (name, (ini_idx, _)) = self.parse_name_spanned()?;
+    let mut segments = Vec::new();
+    let mut offset = 0;
+    for segment in name.split('/') {
+      if segment.is_empty() || segment == ".." {
+        *self.index() = ini_idx + offset;
+        let msg = if segment.is_empty() { "empty path segment" } else { "'..' is not allowed in paths" };
+        return self.expected_and("path segment", msg);
+      }
+      segments.push(segment.to_owned());
+      offset += segment.len() + 1;
+    }
+    Ok(segments)
+  }
+
+  /// Parses a glob pattern like `src/**/*.rs` as a single token, stopping at whitespace or a quote.
+  /// A `\` escapes the next char; escaped glob metacharacters keep their `\` so the result remains a valid glob.
+  fn parse_glob(&mut self) -> Result<String, Self::Error> {
+    self.skip_trivia();
+    self.mark_token_start();
+    let mut glob = String::new();
     while let Some(chr) = self.peek_one() {
-      if chr == '"' {
-        break;
+      self.check_budget()?;
+      match chr {
+        '\\' => {
+          self.advance_one();
+          match self.advance_one() {
+            Some(esc @ ('*' | '?' | '[' | ']' | '\\')) => { glob.push('\\'); glob.push(esc); },
+            Some(esc) => glob.push(esc),
+            None => return self.expected("escaped character"),
+          }
+        }
+        '*' | '?' | '[' | ']' | '/' | '_' | '.' | '-' | '$' => { self.advance_one(); glob.push(chr); },
+        _ if chr.is_alphanumeric() => { self.advance_one(); glob.push(chr); },
+        _ => break,
+      }
+    }
+    self.check_budget()?;
+    if glob.is_empty() {
+      self.expected("glob pattern")
+    } else {
+      Ok(glob)
+    }
+  }
+
+  /// Parses the rest of the input as a template like `a {x} b {{lit}}`, splitting it into literals and `{name}` placeholders.
+  /// `{{` and `}}` stand for literal braces. Errors on an unmatched `}`.
+  fn parse_brace_template(&mut self) -> Result<Vec<TemplatePart>, Self::Error> {
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+    while let Some(chr) = self.peek_one() {
+      self.check_budget()?;
+      if self.starts_with("{{") || self.starts_with("}}") {
+        self.advance_many(2);
+        literal.push(chr);
+      } else if chr == '{' {
+        self.advance_one();
+        if !literal.is_empty() {
+          parts.push(TemplatePart::Literal(core::mem::take(&mut literal)));
+        }
+        parts.push(TemplatePart::Placeholder(self.parse_name()?));
+        self.consume("}")?;
+      } else if chr == '}' {
+        return self.expected_and("}}", "unmatched '}' in template; use '}}' for a literal brace");
       } else {
-        result.push(self.parse_char()?);
+        self.advance_one();
+        literal.push(chr);
       }
     }
-    self.consume("\"")?;
-    Ok(result)
+    self.check_budget()?;
+    if !literal.is_empty() {
+      parts.push(TemplatePart::Literal(literal));
+    }
+    Ok(parts)
+  }
+
+  /// Reads text up to one of the `stops` chars or EOF, collapsing whitespace runs to a single space and trimming the edges.
+  /// The stop char isn't consumed. Like in HTML, `  a   b  ` becomes `a b`.
+  fn parse_inline_text(&mut self, stops: &[char]) -> Result<String, Self::Error> {
+    self.mark_token_start();
+    let text = self.take_while(|c| !stops.contains(&c));
+    self.check_budget()?;
+    Ok(text.split_whitespace().collect::<Vec<_>>().join(" "))
+  }
+
+  /// Parses a boolean flag: `true/false`, `yes/no`, `on/off` or `1/0`, case-insensitively.
+  fn parse_flag(&mut self) -> Result<bool, Self::Error> {
+    self.skip_trivia();
+    self.mark_token_start();
+    let ini_idx = *self.index();
+    let word = self.take_while_ascii(|b| b.is_ascii_alphanumeric() || b == b'_');
+    self.check_budget()?;
+    match word.to_ascii_lowercase().as_str() {
+      "true" | "yes" | "on" | "1" => Ok(true),
+      "false" | "no" | "off" | "0" => Ok(false),
+      _ => {
+        *self.index() = ini_idx;
+        self.expected("true | false | yes | no | on | off | 1 | 0")
+      }
+    }
+  }
+
+  /// Parses a URI-like token, like `https://a.com/x?y=1` or `me@host.com`, returning it raw.
+  /// Stops at whitespace, quotes, and brackets. Doesn't validate the URI structure.
+  fn parse_uri(&mut self) -> Result<String, Self::Error> {
+    self.skip_trivia();
+    self.mark_token_start();
+    let uri = self.take_while_ascii(|b| b.is_ascii_alphanumeric() || b"-._~:/?#@!$&*+,;=%".contains(&b));
+    self.check_budget()?;
+    if uri.is_empty() {
+      self.expected("uri")
+    } else {
+      Ok(uri.to_owned())
+    }
+  }
+
+  /// Parses a name like `parse_name`, returning it along with its byte span.
+  fn parse_name_spanned(&mut self) -> Result<(String, (usize, usize)), Self::Error> {
+    self.with_span(|p| p.parse_name())
+  }
+
+  /// Parses a u64 from the input, supporting dec, hex (0xNUM), and bin (0bNUM).
+  fn parse_u64(&mut self) -> Result<u64, Self::Error> {
+    self.skip_trivia();
+    self.mark_token_start();
+    let radix = match self.peek_many(2) {
+      Some("0x") => { self.advance_many(2); 16 },
+      Some("0b") => { self.advance_many(2); 2 },
+      _ => { 10 },
+    };
+    let num_str = self.take_while(move |c| c.is_digit(radix) || c == '_');
+    self.check_budget()?;
+    let num_str = num_str.chars().filter(|c| *c != '_').collect::<String>();
+    let rest = self.input().get(*self.index()..).unwrap_or_default();
+    let exp = rest.strip_prefix(['e', 'E']).map(|r| r.strip_prefix(['+', '-']).unwrap_or(r));
+    if radix == 10 && !num_str.is_empty() && exp.is_some_and(|r| r.starts_with(|c: char| c.is_ascii_digit())) {
+      return self.expected_and("integer", "integer literal cannot have exponent; did you mean a float?");
+    }
+    if num_str.is_empty() {
+      self.expected("numeric digit")
+    } else {
+      u64::from_str_radix(&num_str, radix)
+        .map_err(|e| self.expected_and::<u64>("integer", &e.to_string()).unwrap_err())
+    }
+  }
+
+  /// Parses an i64 with an optional `+`/`-` sign, accepting the same forms as `parse_u64`.
+  fn parse_i64(&mut self) -> Result<i64, Self::Error> {
+    self.skip_trivia();
+    let ini_idx = *self.index();
+    let sign = self.parse_sign();
+    let mag = self.parse_u64()?;
+    *self.token_start() = ini_idx;
+    let val = if sign < 0 { -(mag as i128) } else { mag as i128 };
+    match i64::try_from(val) {
+      Ok(val) => Ok(val),
+      Err(_) => {
+        let span = (ini_idx, *self.index());
+        self.expected_and_at(span, "integer", "integer out of range for i64")
+      }
+    }
+  }
+
+  /// Parses a range like `1..10`, or `1..=10` if inclusive, returning its start, end, and inclusiveness.
+  fn parse_range(&mut self) -> Result<(i64, i64, bool), Self::Error> {
+    let start = self.parse_i64()?;
+    self.consume("..")?;
+    let inclusive = self.eat_if(|c| c == '=').is_some();
+    let end = self.parse_i64()?;
+    Ok((start, end, inclusive))
+  }
+
+  /// Parses a based integer like `16#FF` or `2r1010`: a decimal base from 2 to 36, a `#` or `r`, then digits in that base.
+  fn parse_based_int(&mut self) -> Result<u64, Self::Error> {
+    self.skip_trivia();
+    self.mark_token_start();
+    let ini_idx = *self.index();
+    let base_str = self.take_while_ascii(|b| b.is_ascii_digit());
+    if base_str.is_empty() {
+      return self.expected("numeric digit");
+    }
+    let base_span = (ini_idx, *self.index());
+    if self.eat_if(|c| c == '#' || c == 'r').is_none() {
+      return self.expected("'#' or 'r'");
+    }
+    let base = match base_str.parse::<u32>() {
+      Ok(base) if (2..=36).contains(&base) => base,
+      _ => {
+        let msg = format!("invalid base {} (must be between 2 and 36)", base_str);
+        return self.expected_and_at(base_span, "base", &msg);
+      }
+    };
+    let num_idx = *self.index();
+    let num_str = self.take_while_ascii(|b| b.is_ascii_alphanumeric() || b == b'_');
+    self.check_budget()?;
+    if let Some(i) = num_str.find(|c: char| c != '_' && !c.is_digit(base)) {
+      let digit = num_str[i..].chars().next().unwrap();
+      let span = (num_idx + i, num_idx + i + 1);
+      return self.expected_and_at(span, "digit", &format!("invalid digit '{}' for base {}", digit, base));
+    }
+    let num_str = num_str.replace('_', "");
+    if num_str.is_empty() {
+      *self.index() = num_idx;
+      return self.expected(&format!("base {} digit", base));
+    }
+    match u64::from_str_radix(&num_str, base) {
+      Ok(num) => Ok(num),
+      Err(e) => {
+        let span = (ini_idx, *self.index());
+        self.expected_and_at(span, "integer", &e.to_string())
+      }
+    }
+  }
+
+  /// Parses a color like `#rgb`, `#rgba`, `#rrggbb`, or `#rrggbbaa`, returning its RGBA components.
+  /// Short forms have each digit doubled, and a missing alpha defaults to 255.
+  fn parse_hex_color(&mut self) -> Result<(u8, u8, u8, u8), Self::Error> {
+    self.skip_trivia();
+    self.mark_token_start();
+    self.consume("#")?;
+    let ini_idx = *self.index();
+    let digits = self.take_while_ascii(|b| b.is_ascii_hexdigit());
+    self.check_budget()?;
+    let width = match digits.len() {
+      3 | 4 => 1,
+      6 | 8 => 2,
+      0 => return self.expected("hex digit"),
+      len => {
+        let msg = format!("unsupported hex color length {} (expected 3, 4, 6, or 8 digits)", len);
+        let span = (ini_idx, *self.index());
+        return self.expected_and_at(span, "hex color", &msg);
+      }
+    };
+    let mut parts = digits.as_bytes().chunks(width).map(|part| {
+      let val = part.iter().fold(0, |acc, b| acc * 16 + (*b as char).to_digit(16).unwrap() as u8);
+      if width == 1 { val * 17 } else { val }
+    });
+    let (r, g, b) = (parts.next().unwrap(), parts.next().unwrap(), parts.next().unwrap());
+    Ok((r, g, b, parts.next().unwrap_or(255)))
+  }
+
+  /// Parses an ISO 8601 timestamp like `2024-01-02T15:04:05`, returning year, month, day, hour, minute, and second.
+  /// Each field must have a fixed number of digits and be in range, else the error points at that field.
+  #[allow(clippy::type_complexity)]
+  fn parse_iso_datetime(&mut self) -> Result<(i32, u32, u32, u32, u32, u32), Self::Error> {
+    self.skip_trivia();
+    self.mark_token_start();
+    let field = |p: &mut Self, sep: Option<char>, name: &str, len: usize, (min, max): (u32, u32)| -> Result<u32, Self::Error> {
+      if let Some(sep) = sep {
+        if p.eat_if(|c| c == sep).is_none() {
+          return p.expected(&sep.to_string());
+        }
+      }
+      let ini_idx = *p.index();
+      let digits = p.input().get(ini_idx..ini_idx + len).filter(|d| d.bytes().all(|b| b.is_ascii_digit()));
+      let Some(digits) = digits else {
+        return p.expected(&format!("{}-digit {}", len, name));
+      };
+      p.advance_many(len);
+      p.check_budget()?;
+      let val = digits.parse::<u32>().unwrap();
+      if val < min || val > max {
+        let msg = format!("{} {} out of range ({} to {})", name, val, min, max);
+        return p.expected_and_at((ini_idx, ini_idx + len), name, &msg);
+      }
+      Ok(val)
+    };
+    let year = field(self, None, "year", 4, (0, 9999))?;
+    let month = field(self, Some('-'), "month", 2, (1, 12))?;
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let days = match month {
+      2 if leap => 29,
+      2 => 28,
+      4 | 6 | 9 | 11 => 30,
+      _ => 31,
+    };
+    let day = field(self, Some('-'), "day", 2, (1, days))?;
+    let hour = field(self, Some('T'), "hour", 2, (0, 23))?;
+    let minute = field(self, Some(':'), "minute", 2, (0, 59))?;
+    let second = field(self, Some(':'), "second", 2, (0, 59))?;
+    Ok((year as i32, month, day, hour, minute, second))
   }
 
+  /// Parses a u64 in the given radix (2 to 36), without consuming any `0x`/`0b` prefix.
+  fn parse_u64_radix(&mut self, radix: u32) -> Result<u64, Self::Error> {
+    self.skip_trivia();
+    self.mark_token_start();
+    if !(2..=36).contains(&radix) {
+      return self.expected_and("integer", &format!("invalid radix {} (must be between 2 and 36)", radix));
+    }
+    let num_str = self.take_while(move |c| c.is_digit(radix) || c == '_');
+    self.check_budget()?;
+    let num_str = num_str.chars().filter(|c| *c != '_').collect::<String>();
+    if num_str.is_empty() {
+      self.expected("numeric digit")
+    } else {
+      u64::from_str_radix(&num_str, radix)
+        .map_err(|e| self.expected_and::<u64>("integer", &e.to_string()).unwrap_err())
+    }
+  }
+
+  /// Parses a u64 like `parse_u64`, but rejects leading, trailing and doubled underscores.
+  fn parse_u64_strict(&mut self) -> Result<u64, Self::Error> {
+    self.skip_trivia();
+    let ini_idx = *self.index();
+    let radix = match self.peek_many(2) {
+      Some("0x") => { self.advance_many(2); 16 },
+      Some("0b") => { self.advance_many(2); 2 },
+      _ => { 10 },
+    };
+    let dig_idx = *self.index();
+    let num_str = self.take_while(move |c| c.is_digit(radix) || c == '_');
+    let bad_pos = if num_str.starts_with('_') {
+      Some(0)
+    } else if let Some(pos) = num_str.find("__") {
+      Some(pos + 1)
+    } else if num_str.ends_with('_') {
+      Some(num_str.len() - 1)
+    } else {
+      None
+    };
+    if let Some(pos) = bad_pos {
+      *self.index() = dig_idx + pos;
+      return self.expected_and("numeric digit", "misplaced underscore in numeric literal");
+    }
+    *self.index() = ini_idx;
+    self.parse_u64()
+  }
+
+  /// Parses a f64 that has a fractional part or an exponent, like `3.0` or `3e2`.
+  /// On failure, leaves the index unchanged, so the caller can fall back to an integer parser.
+  fn parse_strict_float(&mut self) -> Result<f64, Self::Error> {
+    let old_idx = *self.index();
+    self.skip_trivia();
+    self.mark_token_start();
+    let ini_idx = *self.index();
+    let digits = |c: char| c.is_ascii_digit() || c == '_';
+    let int_part = self.take_while(digits);
+    let mut is_float = false;
+    if int_part.starts_with(|c: char| c.is_ascii_digit()) {
+      if self.starts_with(".") && self.input()[*self.index() + 1..].starts_with(|c: char| c.is_ascii_digit()) {
+        self.advance_one();
+        self.take_while(digits);
+        is_float = true;
+      }
+      let exp_idx = *self.index();
+      if self.eat_if(|c| c == 'e' || c == 'E').is_some() {
+        self.eat_if(|c| c == '+' || c == '-');
+        if self.take_while(digits).is_empty() {
+          *self.index() = exp_idx;
+        } else {
+          is_float = true;
+        }
+      }
+    }
+    let num_str = self.input()[ini_idx..*self.index()].replace('_', "");
+    match num_str.parse::<f64>() {
+      Ok(num) if is_float && !self.budget_exceeded() => Ok(num),
+      _ => {
+        *self.index() = ini_idx;
+        let err = self.expected::<f64>("float").unwrap_err();
+        *self.index() = old_idx;
+        Err(err)
+      }
+    }
+  }
+
+  /// Parses a number followed by an optional unit, like `10px`, `-1.5em`, `50%` or `42`, returning the value and unit.
+  /// The unit is a run of ASCII letters or a `%`, right after the number. Dimensionless numbers get an empty unit.
+  fn parse_quantity(&mut self) -> Result<(f64, String), Self::Error> {
+    let sign = self.parse_sign();
+    let value = match self.parse_strict_float() {
+      Ok(value) => value,
+      Err(_) => {
+        self.mark_token_start();
+        let digits = self.take_while_ascii(|b| b.is_ascii_digit() || b == b'_');
+        if !digits.starts_with(|c: char| c.is_ascii_digit()) {
+          *self.index() -= digits.len();
+          return self.expected("number");
+        }
+        digits.replace('_', "").parse::<f64>().unwrap()
+      }
+    };
+    let unit = if self.eat_if(|c| c == '%').is_some() {
+      "%"
+    } else {
+      self.take_while_ascii(|b| b.is_ascii_alphabetic())
+    };
+    self.check_budget()?;
+    Ok((value * sign as f64, unit.to_owned()))
+  }
+
+  /// Parses a number with comma thousands separators, like `-1,234.56`, as a f64.
+  /// Commas are optional, but if present every group after the first must have exactly 3 digits.
+  fn parse_grouped_decimal(&mut self) -> Result<f64, Self::Error> {
+    let sign = self.parse_sign();
+    self.mark_token_start();
+    let ini_idx = *self.index();
+    let mut num_str = self.take_while_ascii(|b| b.is_ascii_digit()).to_owned();
+    if num_str.is_empty() {
+      return self.expected("numeric digit");
+    }
+    let mut grouped = false;
+    while self.starts_with(",") && self.input()[*self.index() + 1..].starts_with(|c: char| c.is_ascii_digit()) {
+      if !grouped && num_str.len() > 3 {
+        let span = (ini_idx, ini_idx + num_str.len());
+        return self.expected_and_at(span, "digit group", "leading thousands group must have at most 3 digits");
+      }
+      grouped = true;
+      self.advance_one();
+      let grp_idx = *self.index();
+      let group = self.take_while_ascii(|b| b.is_ascii_digit());
+      if group.len() != 3 {
+        let span = (grp_idx, *self.index());
+        return self.expected_and_at(span, "digit group", "thousands group must have exactly 3 digits");
+      }
+      num_str.push_str(group);
+    }
+    if self.starts_with(".") && self.input()[*self.index() + 1..].starts_with(|c: char| c.is_ascii_digit()) {
+      self.advance_one();
+      num_str.push('.');
+      num_str.push_str(self.take_while_ascii(|b| b.is_ascii_digit()));
+    }
+    self.check_budget()?;
+    Ok(num_str.parse::<f64>().unwrap() * sign as f64)
+  }
+
+  /// Parses an exact decimal like `-3.14`, returning its unscaled mantissa and scale, like `(-314, 2)`.
+  /// Accepts an optional sign and underscores between digits.
+  fn parse_decimal(&mut self) -> Result<(i128, u32), Self::Error> {
+    let sign = self.parse_sign();
+    self.mark_token_start();
+    let digits = |c: char| c.is_ascii_digit() || c == '_';
+    let int_part = self.take_while(digits);
+    if !int_part.starts_with(|c: char| c.is_ascii_digit()) {
+      return self.expected("numeric digit");
+    }
+    let mut frac_part = "";
+    if self.starts_with(".") && self.input()[*self.index() + 1..].starts_with(|c: char| c.is_ascii_digit()) {
+      self.advance_one();
+      frac_part = self.take_while(digits);
+    }
+    let mut mantissa: i128 = 0;
+    for chr in int_part.chars().chain(frac_part.chars()).filter(|c| *c != '_') {
+      let digit = chr.to_digit(10).unwrap() as i128;
+      match mantissa.checked_mul(10).and_then(|m| m.checked_add(digit)) {
+        Some(m) => mantissa = m,
+        None => return self.expected_and("decimal", "decimal literal is too large"),
+      }
+    }
+    let scale = frac_part.chars().filter(|c| *c != '_').count() as u32;
+    self.check_budget()?;
+    Ok((mantissa * sign as i128, scale))
+  }
+
+  /// Parses a semantic version like `1.2.3-alpha.1+build`, returning
+  /// the major, minor and patch numbers, plus the optional pre-release and build metadata.
+  #[allow(clippy::type_complexity)]
+  fn parse_semver(&mut self) -> Result<(u64, u64, u64, Option<String>, Option<String>), Self::Error> {
+    self.skip_trivia();
+    self.mark_token_start();
+    let mut nums = [0; 3];
+    for (i, num) in nums.iter_mut().enumerate() {
+      if i > 0 && self.eat_if(|c| c == '.').is_none() {
+        return self.expected(".");
+      }
+      let num_str = self.take_while(|c| c.is_ascii_digit());
+      if num_str.is_empty() {
+        return self.expected("numeric digit");
+      }
+      *num = num_str.parse::<u64>()
+        .map_err(|e| self.expected_and::<u64>("integer", &e.to_string()).unwrap_err())?;
+    }
+    let mut extra = [None, None];
+    for (sep, part) in ['-', '+'].into_iter().zip(extra.iter_mut()) {
+      if self.eat_if(|c| c == sep).is_some() {
+        let ident = self.take_while(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-');
+        if ident.split('.').any(|s| s.is_empty()) {
+          return self.expected("version identifier");
+        }
+        *part = Some(ident.to_owned());
+      }
+    }
+    let [pre, build] = extra;
+    self.check_budget()?;
+    Ok((nums[0], nums[1], nums[2], pre, build))
+  }
+
+  /// Consumes an optional `+`/`-` sign, then runs `f`, negating its result on `-`.
+  fn signed<T: core::ops::Neg<Output = T>>(&mut self, f: impl FnOnce(&mut Self) -> Result<T, Self::Error>) -> Result<T, Self::Error> {
+    let sign = self.parse_sign();
+    let val = f(self)?;
+    Ok(if sign < 0 { -val } else { val })
+  }
+
+  /// Consumes an optional `+`/`-` sign, returning `-1` for `-` and `1` otherwise.
+  fn parse_sign(&mut self) -> i8 {
+    self.skip_trivia();
+    match self.peek_one() {
+      Some('-') => { self.advance_one(); -1 },
+      Some('+') => { self.advance_one(); 1 },
+      _ => { 1 },
+    }
+  }
+
+  /// Parses a single unicode character, supporting scape sequences.
+  fn parse_char(&mut self) -> Result<char, Self::Error> {
+    match self.advance_one() {
+      Some('\\') => match self.advance_one() {
+        Some('u') => {
+          if self.peek_one() != Some('{') {
+            return self.expected("{");
+          }
+          self.advance_one();
+          let ini_idx = *self.index();
+          let codepoint_str = self.take_while(|c| c.is_ascii_hexdigit());
+          if codepoint_str.is_empty() {
+            return self.expected("hex digit");
+          }
+          if codepoint_str.len() > 6 {
+            *self.index() = ini_idx;
+            return self.expected_and("unicode-codepoint", "unicode escape must have at most 6 hex digits");
+          }
+          if self.peek_one() != Some('}') {
+            return self.expected("}");
+          }
+          self.advance_one();
+          let codepoint = u32::from_str_radix(codepoint_str, 16).unwrap();
+          match core::char::from_u32(codepoint) {
+            Some(chr) => Ok(chr),
+            None => {
+              *self.index() = ini_idx;
+              let msg = if (0xD800..=0xDFFF).contains(&codepoint) {
+                format!("lone surrogate U+{:04X} is not a valid character", codepoint)
+              } else {
+                format!("U+{:04X} exceeds the maximum (U+10FFFF)", codepoint)
+              };
+              self.expected_and("unicode-codepoint", &msg)
+            }
+          }
+        }
+        Some('0') => Ok('\0'),
+        Some('n') => Ok('\n'),
+        Some('r') => Ok('\r'),
+        Some('t') => Ok('\t'),
+        Some('\'') => Ok('\''),
+        Some('\"') => Ok('\"'),
+        Some('\\') => Ok('\\'),
+        Some(chr) => self.expected(&format!("\\{}", chr)),
+        None => self.expected("escaped-char"),
+      },
+      Some(other) => Ok(other),
+      None => self.expected("char"),
+    }
+  }
+
+  /// Parses a single unicode character, letting `escape` handle the char after a backslash.
+  /// If `escape` returns `None`, falls back to the default escapes of `parse_char`.
+  fn parse_char_with(&mut self, escape: impl FnOnce(char, &mut Self) -> Result<Option<char>, Self::Error>) -> Result<char, Self::Error> {
+    let ini_idx = *self.index();
+    if self.peek_one() == Some('\\') {
+      self.advance_one();
+      if let Some(chr) = self.advance_one() {
+        if let Some(custom) = escape(chr, self)? {
+          return Ok(custom);
+        }
+      }
+      *self.index() = ini_idx;
+    }
+    self.parse_char()
+  }
+
+  /// Parses a quoted character, like 'x'. Rejects literals with more than one character, like 'ab'.
+  fn parse_quoted_char(&mut self) -> Result<char, Self::Error> {
+    self.skip_trivia();
+    self.mark_token_start();
+    self.consume("'")?;
+    if self.peek_one() == Some('\'') {
+      *self.index() -= 1;
+      return self.expected_and("char", "empty character literal");
+    }
+    let chr = self.parse_char()?;
+    // Like rustc, only a contiguous run of chars up to a `'` is taken as an overlong literal, so `'a, 'b'` isn't one
+    let rest = self.input().get(*self.index()..).unwrap_or_default();
+    let run = rest.find(|c: char| c.is_whitespace() || c == '\'').unwrap_or(rest.len());
+    if run > 0 && rest[run..].starts_with('\'') {
+      return self.expected_and("'", "character literal may only contain one character");
+    }
+    self.consume("'")?;
+    Ok(chr)
+  }
+
+  /// Parses a quoted string, like "foobar".
+  fn parse_quoted_string(&mut self) -> Result<String, Self::Error> {
+    self.skip_trivia();
+    self.mark_token_start();
+    self.consume("\"")?;
+    let mut result = String::new();
+    self.parse_quoted_chars(|_, chr, _| {
+      result.push(chr);
+      Ok(())
+    })?;
+    self.consume("\"")?;
+    Ok(result)
+  }
+
+  /// Decodes chars with `parse_char` up to a `"`, which isn't consumed, passing each to `on_char`
+  /// along with the byte index where its source began. Shared by the `parse_quoted_string` variants.
+  fn parse_quoted_chars(&mut self, mut on_char: impl FnMut(&mut Self, char, usize) -> Result<(), Self::Error>) -> Result<(), Self::Error> {
+    while let Some(chr) = self.peek_one() {
+      if chr == '"' {
+        break;
+      }
+      let idx = *self.index();
+      let chr = self.parse_char()?;
+      on_char(self, chr, idx)?;
+    }
+    Ok(())
+  }
+
+  /// Parses a quoted string like `parse_quoted_string`, erroring once it decodes to more than `max_len` chars.
+  fn parse_quoted_string_max(&mut self, max_len: usize) -> Result<String, Self::Error> {
+    self.skip_trivia();
+    self.mark_token_start();
+    self.consume("\"")?;
+    let mut result = String::new();
+    let mut len = 0;
+    self.parse_quoted_chars(|p, chr, idx| {
+      if len == max_len {
+        *p.index() = idx;
+        return p.expected_and("\"", &format!("string literal too long (limit is {} chars)", max_len));
+      }
+      result.push(chr);
+      len += 1;
+      Ok(())
+    })?;
+    self.consume("\"")?;
+    Ok(result)
+  }
+
+  /// Parses a quoted string like `parse_quoted_string`, pairing each decoded char with the byte index where its source began.
+  /// For escapes, that is the index of the `\`.
+  fn parse_quoted_string_mapped(&mut self) -> Result<Vec<(char, usize)>, Self::Error> {
+    self.skip_trivia();
+    self.mark_token_start();
+    self.consume("\"")?;
+    let mut result = Vec::new();
+    self.parse_quoted_chars(|_, chr, idx| {
+      result.push((chr, idx));
+      Ok(())
+    })?;
+    self.consume("\"")?;
+    Ok(result)
+  }
+
+  /// Parses a quoted string like `parse_quoted_string`, borrowing from the input when it has no escapes.
+  fn parse_quoted_string_cow(&mut self) -> Result<Cow<'i, str>, Self::Error> {
+    self.skip_trivia();
+    self.mark_token_start();
+    self.consume("\"")?;
+    let raw = self.take_while(|c| c != '"' && c != '\\');
+    let result = if self.peek_one() == Some('\\') {
+      let mut result = raw.to_owned();
+      self.parse_quoted_chars(|_, chr, _| {
+        result.push(chr);
+        Ok(())
+      })?;
+      Cow::Owned(result)
+    } else {
+      Cow::Borrowed(raw)
+    };
+    self.consume("\"")?;
+    Ok(result)
+  }
+
+  /// Parses a quoted string like `parse_quoted_string`, but errors on a raw newline before the closing quote.
+  fn parse_quoted_string_single_line(&mut self) -> Result<String, Self::Error> {
+    self.skip_trivia();
+    self.mark_token_start();
+    self.consume("\"")?;
+    let mut result = String::new();
+    self.parse_quoted_chars(|p, chr, idx| {
+      if p.input()[idx..].starts_with('\n') {
+        *p.index() = idx;
+        return p.expected_and("\"", "unterminated string literal");
+      }
+      result.push(chr);
+      Ok(())
+    })?;
+    self.consume("\"")?;
+    Ok(result)
+  }
+
+  /// Parses a `"""`-delimited block string, taken raw, stripping the indentation of the closing `"""` from each line.
+  /// If the closing `"""` isn't on its own line, the minimum indentation of the non-empty lines is stripped instead.
+  /// A newline right after the opening `"""` is dropped. Errors on a line indented less than the closing `"""`.
+  fn parse_block_string(&mut self) -> Result<String, Self::Error> {
+    self.skip_trivia();
+    self.mark_token_start();
+    self.consume("\"\"\"")?;
+    let ini_idx = *self.index();
+    let Some(len) = self.input()[ini_idx..].find("\"\"\"") else {
+      *self.index() = self.input().len();
+      return self.expected("\"\"\"");
+    };
+    let body = &self.input()[ini_idx..ini_idx + len];
+    let (body, body_idx) = match body.strip_prefix('\n') {
+      Some(rest) => (rest, ini_idx + 1),
+      None => (body, ini_idx),
+    };
+    let is_indent = |c: char| c == ' ' || c == '\t';
+    let (lines, strip) = match body.rsplit_once('\n') {
+      Some((lines, last)) if last.chars().all(is_indent) => (lines, Some(last.len())),
+      _ => (body, None),
+    };
+    let indent = |line: &str| line.len() - line.trim_start_matches(is_indent).len();
+    let strip = strip.unwrap_or_else(|| {
+      lines.lines().filter(|l| !l.trim().is_empty()).map(indent).min().unwrap_or(0)
+    });
+    let mut result = Vec::new();
+    let mut line_idx = body_idx;
+    for line in lines.split('\n') {
+      if line.trim().is_empty() {
+        result.push("");
+      } else if indent(line) < strip {
+        *self.index() = line_idx + indent(line);
+        return self.expected_and("indentation", "line is indented less than the closing \"\"\"");
+      } else {
+        result.push(&line[strip..]);
+      }
+      line_idx += line.len() + 1;
+    }
+    let chars = self.input()[ini_idx..ini_idx + len].chars().count() + 3;
+    self.charge_steps(chars);
+    self.check_budget()?;
+    *self.index() = ini_idx + len + 3;
+    Ok(result.join("\n"))
+  }
+
+  /// Parses a `/pattern/flags` regex literal, returning the raw pattern and the trailing flag letters.
+  /// Escapes are kept as written, and a `/` escaped or inside a `[...]` class doesn't end the pattern.
+  fn parse_regex_literal(&mut self) -> Result<(String, String), Self::Error> {
+    self.skip_trivia();
+    self.mark_token_start();
+    self.consume("/")?;
+    let ini_idx = *self.index();
+    let mut in_class = false;
+    loop {
+      self.check_budget()?;
+      match self.peek_one() {
+        None | Some('\n') => return self.expected_and("/", "unterminated regex literal"),
+        Some('/') if !in_class => break,
+        Some('\\') => {
+          self.advance_one();
+          if matches!(self.peek_one(), None | Some('\n')) {
+            return self.expected_and("/", "unterminated regex literal");
+          }
+          self.advance_one();
+        }
+        Some(chr) => {
+          in_class = (in_class || chr == '[') && chr != ']';
+          self.advance_one();
+        }
+      }
+    }
+    let pattern = &self.input()[ini_idx..*self.index()];
+    if pattern.is_empty() {
+      return self.expected("regex pattern");
+    }
+    self.advance_one();
+    let flags = self.take_while_ascii(|b| b.is_ascii_alphabetic());
+    self.check_budget()?;
+    Ok((pattern.to_owned(), flags.to_owned()))
+  }
+
+  /// Skips past the `close` matching an already consumed `open`, tracking nesting.
+  /// Brackets inside `"`-quoted strings are ignored. Useful to recover from errors.
+  fn skip_to_matching(&mut self, open: char, close: char) -> Result<(), Self::Error> {
+    let mut depth = 1;
+    while let Some(chr) = self.peek_one() {
+      if chr == '"' {
+        self.skip_string('"')?;
+        continue;
+      }
+      if self.advance_one().is_none() {
+        break;
+      }
+      if chr == open {
+        depth += 1;
+      } else if chr == close {
+        depth -= 1;
+        if depth == 0 {
+          return Ok(());
+        }
+      }
+    }
+    self.expected(&close.to_string())
+  }
+
+  /// Parses zero or more `#[...]` attributes separated by trivia, like `#[inline] #[cfg(test)]`, returning their raw inner text.
+  /// Inner brackets must be balanced, using `skip_to_matching`. Trivia after the last attribute is left unconsumed.
+  fn parse_attributes_hashbracket(&mut self) -> Result<Vec<String>, Self::Error> {
+    let mut attrs = Vec::new();
+    loop {
+      let ini_idx = *self.index();
+      self.skip_trivia();
+      if !self.starts_with("#[") {
+        *self.index() = ini_idx;
+        return Ok(attrs);
+      }
+      self.advance_many(2);
+      let inner_idx = *self.index();
+      self.skip_to_matching('[', ']')?;
+      attrs.push(self.input()[inner_idx..*self.index() - 1].to_owned());
+    }
+  }
+
+  /// Skips a string literal delimited by `quote`, without decoding it.
+  /// The cursor must be on the opening quote. Backslash escapes are honored.
+  fn skip_string(&mut self, quote: char) -> Result<(), Self::Error> {
+    if self.peek_one() != Some(quote) {
+      return self.expected(&quote.to_string());
+    }
+    self.advance_one();
+    loop {
+      match self.advance_one() {
+        Some('\\') => { self.advance_one(); }
+        Some(chr) if chr == quote => return Ok(()),
+        Some(_) => {}
+        None => return self.expected_and(&quote.to_string(), "unterminated string literal"),
+      }
+    }
+  }
+
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  new_parser!(TestParser);
+
+  /// Returns the error's message without ANSI codes, for matching.
+  fn plain(err: &ParseError) -> String {
+    strip_ansi(&err.message)
+  }
+
+  #[test]
+  fn unicode_escapes() {
+    assert_eq!(TestParser::new("\\u{1F600}").parse_char().unwrap(), '\u{1F600}');
+    assert_eq!(TestParser::new("\\u{41}").parse_char().unwrap(), 'A');
+    let err = TestParser::new("\\u{}").parse_char().unwrap_err();
+    assert!(plain(&err).contains("hex digit"));
+    let err = TestParser::new("\\u{110000}").parse_char().unwrap_err();
+    assert!(plain(&err).contains("U+110000"));
+    assert_eq!(err.span, (3, 4));
+    let err = TestParser::new("\\u{1234567}").parse_char().unwrap_err();
+    assert!(plain(&err).contains("at most 6 hex digits"));
+  }
+
+  #[test]
+  fn unicode_escape_braces_are_exact() {
+    assert!(TestParser::new("\\u {41}").parse_char().is_err());
+    assert!(TestParser::new("\\u{41 }").parse_char().is_err());
+    assert!(TestParser::new("\"\\u{41 // c\n}\"").parse_quoted_string().is_err());
+  }
+
+  #[test]
+  fn longest_prefers_maximal_munch() {
+    let mut p = TestParser::new(">= 1");
+    let op = p.longest(&mut [&mut |p| p.consume(">").map(|_| ">"), &mut |p| p.consume(">=").map(|_| ">=")]);
+    assert_eq!(op.unwrap(), ">=");
+    assert_eq!(p.index, 2);
+    let mut p = TestParser::new("x");
+    assert!(p.longest(&mut [&mut |p| p.consume(">"), &mut |p| p.consume(">=")]).is_err());
+    assert_eq!(p.index, 0);
+  }
+
+  #[test]
+  fn match_pattern_digits() {
+    let mut p = TestParser::new("123abc");
+    assert_eq!(p.match_pattern("\\d+").unwrap(), "123");
+    assert_eq!(p.index, 3);
+    assert!(p.match_pattern("\\d+").is_err());
+    assert_eq!(p.index, 3);
+    assert_eq!(p.match_pattern("a.c").unwrap(), "abc");
+  }
+
+  #[test]
+  fn nesting_depth_limit() {
+    fn parens(p: &mut TestParser) -> Result<usize, ParseError> {
+      if p.consume("(").is_err() {
+        return Ok(0);
+      }
+      p.enter()?;
+      let depth = parens(p)? + 1;
+      p.leave();
+      p.consume(")")?;
+      Ok(depth)
+    }
+    assert_eq!(parens(&mut TestParser::new("((()))").with_max_depth(3)).unwrap(), 3);
+    let err = parens(&mut TestParser::new("(((())))").with_max_depth(3)).unwrap_err();
+    assert!(plain(&err).contains("nesting too deep (limit is 3)"));
+  }
+
+  #[test]
+  fn consume_line_terminators() {
+    let mut p = TestParser::new("ab\r\ncd");
+    assert_eq!(p.consume_line(), "ab");
+    assert_eq!(p.index, 4);
+    assert_eq!(p.consume_line(), "cd");
+    assert!(p.is_eof());
+    assert_eq!(p.consume_line(), "");
+  }
+
+  #[test]
+  fn signed_wraps_numeric_parser() {
+    assert_eq!(TestParser::new("-7").signed(|p| p.parse_u64().map(|n| n as i64)).unwrap(), -7);
+    assert_eq!(TestParser::new("+0x10").signed(|p| p.parse_u64().map(|n| n as i64)).unwrap(), 16);
+    assert_eq!(TestParser::new("7").signed(|p| p.parse_u64().map(|n| n as i128)).unwrap(), 7);
+  }
+
+  #[test]
+  fn with_span_matches_consumed_text() {
+    let src = "  foo  bar";
+    let mut p = TestParser::new(src);
+    let (name, span) = p.with_span(|p| p.parse_name()).unwrap();
+    assert_eq!(&src[span.0..span.1], name);
+    assert_eq!(span, (2, 5));
+  }
+
+  #[test]
+  fn last_token_start_after_parse_name() {
+    let mut p = TestParser::new("x   name");
+    p.parse_name().unwrap();
+    p.parse_name().unwrap();
+    assert_eq!(p.last_token_start(), 4);
+  }
+
+  #[test]
+  fn strict_underscores() {
+    assert_eq!(TestParser::new("1_000").parse_u64_strict().unwrap(), 1000);
+    let err = TestParser::new("1__000").parse_u64_strict().unwrap_err();
+    assert!(plain(&err).contains("misplaced underscore"));
+    assert_eq!(err.span, (2, 3));
+    assert!(TestParser::new("_5").parse_u64_strict().is_err());
+    assert!(TestParser::new("5_").parse_u64_strict().is_err());
+  }
+
+  #[test]
+  fn radix_parsing() {
+    assert_eq!(TestParser::new("z").parse_u64_radix(36).unwrap(), 35);
+    assert_eq!(TestParser::new("ff").parse_u64_radix(16).unwrap(), 255);
+    let mut p = TestParser::new("12");
+    assert_eq!(p.parse_u64_radix(2).unwrap(), 1);
+    assert_eq!(p.index, 1);
+    assert!(TestParser::new("9").parse_u64_radix(8).is_err());
+    assert!(TestParser::new("1").parse_u64_radix(37).is_err());
+  }
+
+  #[test]
+  fn resolve_errors_on_different_lines() {
+    let src = "ab\ncd\nλx";
+    let errors = [ParseError::new((1, 2), "first"), ParseError::new((8, 9), "\x1b[1msecond\x1b[0m")];
+    let resolved = resolve_errors(&errors, src);
+    assert_eq!(resolved, [(1, 2, "first".to_owned()), (3, 2, "second".to_owned())]);
+    assert_eq!(LineMap::new(src).line_col(3), (2, 1));
+  }
+
+  #[test]
+  fn position_across_newline() {
+    let mut p = TestParser::new("ab\n  λx");
+    p.parse_name().unwrap();
+    p.parse_name().unwrap_err();
+    assert_eq!(p.position(), Position { byte: 5, line: 2, col: 3 });
+  }
+
+  #[test]
+  fn parse_all_requires_full_consumption() {
+    fn lam(p: &mut TestParser) -> Result<(String, String), ParseError> {
+      p.consume("λ")?;
+      let var = p.parse_name()?;
+      Ok((var, p.parse_name()?))
+    }
+    assert!(TestParser::new("λx x").parse_all(lam).is_ok());
+    assert!(TestParser::new("λx x  ").parse_all(lam).is_ok());
+    let err = TestParser::new("λx x extra").parse_all(lam).unwrap_err();
+    assert!(plain(&err).contains("end of input"));
+    assert_eq!(err.span, (6, 7));
+  }
+
+  #[test]
+  #[cfg(feature = "graphemes")]
+  fn advance_grapheme_over_combining_accent() {
+    let mut p = TestParser::new("e\u{301}x");
+    assert_eq!(p.advance_grapheme(), Some("e\u{301}"));
+    assert_eq!(p.index, 3);
+    assert_eq!(p.advance_grapheme(), Some("x"));
+    assert_eq!(p.advance_grapheme(), None);
+  }
+
+  #[test]
+  #[cfg(feature = "width")]
+  fn display_width_of_full_width_chars() {
+    assert_eq!(display_width("a"), 1);
+    assert_eq!(display_width("日"), 2);
+    assert_eq!(display_width("a日b"), 4);
+    assert_eq!(display_width("e\u{301}"), 1);
+  }
+
+  #[test]
+  fn consume_repeated_hashes() {
+    let mut p = TestParser::new("#### title");
+    assert_eq!(p.consume_repeated("#"), 4);
+    assert_eq!(p.index, 4);
+    assert_eq!(p.consume_repeated("#"), 0);
+    assert_eq!(TestParser::new("ababa").consume_repeated("ab"), 2);
+  }
+
+  #[test]
+  fn parse_char_with_custom_escape() {
+    let custom = |chr: char, _: &mut TestParser| Ok(if chr == 'q' { Some('?') } else { None });
+    assert_eq!(TestParser::new("\\q").parse_char_with(custom).unwrap(), '?');
+    assert_eq!(TestParser::new("\\n").parse_char_with(custom).unwrap(), '\n');
+    assert_eq!(TestParser::new("a").parse_char_with(custom).unwrap(), 'a');
+  }
+
+  #[test]
+  fn eat_if_digit() {
+    let mut p = TestParser::new("1a");
+    assert_eq!(p.eat_if(|c| c.is_ascii_digit()), Some('1'));
+    assert_eq!(p.eat_if(|c| c.is_ascii_digit()), None);
+    assert_eq!(p.index, 1);
+  }
+
+  #[test]
+  fn parse_name_spanned_after_whitespace() {
+    let src = "   foo bar";
+    let (name, span) = TestParser::new(src).parse_name_spanned().unwrap();
+    assert_eq!(name, "foo");
+    assert_eq!(span, (3, 6));
+    assert_eq!(&src[span.0..span.1], "foo");
+  }
+
+  #[test]
+  fn parse_sign_variants() {
+    let mut p = TestParser::new("+1");
+    assert_eq!(p.parse_sign(), 1);
+    assert_eq!(p.index, 1);
+    let mut p = TestParser::new("-1");
+    assert_eq!(p.parse_sign(), -1);
+    assert_eq!(p.index, 1);
+    let mut p = TestParser::new("1");
+    assert_eq!(p.parse_sign(), 1);
+    assert_eq!(p.index, 0);
+  }
+
+  #[test]
+  fn parse_semver_parts() {
+    assert_eq!(TestParser::new("1.2.3").parse_semver().unwrap(), (1, 2, 3, None, None));
+    assert_eq!(TestParser::new("1.0.0-rc.1").parse_semver().unwrap(), (1, 0, 0, Some("rc.1".to_owned()), None));
+    assert_eq!(TestParser::new("2.0.0+sha.abc").parse_semver().unwrap(), (2, 0, 0, None, Some("sha.abc".to_owned())));
+    assert!(TestParser::new("1.2").parse_semver().is_err());
+  }
+
+  #[test]
+  fn prev_char_after_multibyte() {
+    let mut p = TestParser::new("λx");
+    assert_eq!(p.prev_char(), None);
+    p.advance_one();
+    assert_eq!(p.index, 2);
+    assert_eq!(p.prev_char(), Some('λ'));
+  }
+
+  #[test]
+  fn trim_trailing_trivia_spans() {
+    let src = "foo  // c\n  bar";
+    assert_eq!(trim_trailing_trivia((0, 12), src), (0, 3));
+    assert_eq!(trim_trailing_trivia((0, 5), src), (0, 3));
+    assert_eq!(trim_trailing_trivia((12, 15), src), (12, 15));
+    assert_eq!(trim_trailing_trivia((0, 99), "ab "), (0, 2));
+    assert_eq!(trim_trailing_trivia((0, 1), "λ"), (0, 0));
+    assert_eq!(trim_trailing_trivia((0, 18), "x = https://a.com\n"), (0, 17));
+  }
+
+  #[test]
+  fn with_span_keeps_url_before_newline() {
+    let src = "go https://a.com\n  // c\nnext";
+    let mut p = TestParser::new(src);
+    let (_, span) = p.with_span(|p| {
+      p.consume("go")?;
+      p.skip_trivia();
+      p.take_while(|c| !c.is_whitespace());
+      p.skip_trivia();
+      Ok(())
+    }).unwrap();
+    assert_eq!(span, (0, 16));
+    assert_eq!(p.recognize(|p| p.parse_name()).unwrap(), "next");
+  }
+
+  #[test]
+  fn parse_backtick_ident_escapes() {
+    assert_eq!(TestParser::new("`col name`").parse_backtick_ident().unwrap(), "col name");
+    assert_eq!(TestParser::new("`a``b`").parse_backtick_ident().unwrap(), "a`b");
+    assert!(TestParser::new("``").parse_backtick_ident().is_err());
+  }
+
+  #[test]
+  fn comment_inner_text() {
+    let mut p = TestParser::new("// hi\nx");
+    assert_eq!(p.parse_line_comment(), Some(" hi"));
+    assert_eq!(p.index, 6);
+    let mut p = TestParser::new("/* a */x");
+    assert_eq!(p.parse_block_comment(), Some(" a "));
+    assert_eq!(p.index, 7);
+    assert_eq!(TestParser::new("/* a").parse_block_comment(), None);
+  }
+
+  #[test]
+  fn chars_left_counts_chars() {
+    let mut p = TestParser::new("a😀b");
+    assert_eq!(p.chars_left(), 3);
+    p.advance_one();
+    assert_eq!(p.chars_left(), 2);
+    p.advance_one();
+    assert_eq!(p.chars_left(), 1);
+  }
+
+  #[test]
+  fn strict_float_rejects_integers() {
+    let mut p = TestParser::new(" 3");
+    assert!(p.parse_strict_float().is_err());
+    assert_eq!(p.index, 0);
+    assert_eq!(TestParser::new("3.0").parse_strict_float().unwrap(), 3.0);
+    assert_eq!(TestParser::new("3e2").parse_strict_float().unwrap(), 300.0);
+  }
+
+  #[test]
+  fn classify_keyword_vs_name() {
+    const KWS: &[&str] = &["in", "let", "match"];
+    let mut p = TestParser::new("let inner");
+    let name = p.parse_name().unwrap();
+    assert_eq!(p.classify_keyword(&name, KWS), Some(1));
+    let name = p.parse_name().unwrap();
+    assert_eq!(p.classify_keyword(&name, KWS), None);
+  }
+
+  #[test]
+  fn shift_error_around_edit() {
+    let before = ParseError::new((2, 3), "before");
+    let after = ParseError::new((10, 12), "after");
+    assert_eq!(shift_error(&before, 5, 4).span, (2, 3));
+    assert_eq!(shift_error(&after, 5, 4).span, (14, 16));
+    assert_eq!(shift_error(&after, 5, -3).span, (7, 9));
+    assert_eq!(shift_error(&after, 5, 4).message, "after");
+  }
+
+  #[test]
+  fn single_line_string_rejects_newline() {
+    let err = TestParser::new("\"ab\ncd\"").parse_quoted_string_single_line().unwrap_err();
+    assert!(plain(&err).contains("unterminated string literal"));
+    assert_eq!(err.span, (3, 4));
+    assert_eq!(TestParser::new("\"a\\nb\"").parse_quoted_string_single_line().unwrap(), "a\nb");
+  }
+
+  #[test]
+  fn skip_string_honors_escapes() {
+    let mut p = TestParser::new("\"a\\\"b\" x");
+    p.skip_string('"').unwrap();
+    assert_eq!(p.index, 6);
+    assert!(TestParser::new("\"ab").skip_string('"').is_err());
+  }
+
+  #[test]
+  fn quoted_string_cow_borrows_without_escapes() {
+    let plain_str = TestParser::new("\"abc\"").parse_quoted_string_cow().unwrap();
+    assert!(matches!(plain_str, Cow::Borrowed("abc")));
+    let escaped = TestParser::new("\"a\\tb\"").parse_quoted_string_cow().unwrap();
+    assert!(matches!(&escaped, Cow::Owned(s) if s == "a\tb"));
+  }
+
+  #[test]
+  fn take_while_ascii_matches_take_while() {
+    let src = "abc_1.x-y/$z  rest\tλ";
+    let mut fast = TestParser::new(src);
+    let mut slow = TestParser::new(src);
+    while !slow.is_eof() {
+      let a = fast.take_while_ascii(|b| is_name_char(b as char));
+      let b = slow.take_while(is_name_char);
+      assert_eq!(a, b);
+      assert_eq!(fast.index, slow.index);
+      fast.advance_one();
+      slow.advance_one();
+    }
+  }
+
+  #[test]
+  fn parse_name_stops_at_non_name_chars() {
+    let mut p = TestParser::new("  foo_1.bar-baz/$x(y)");
+    assert_eq!(p.parse_name().unwrap(), "foo_1.bar-baz/$x");
+    assert_eq!(p.index, 18);
+    assert!(TestParser::new("λx").parse_name().is_err());
+    assert_eq!(TestParser::new("aλ").parse_name().unwrap(), "a");
+  }
+
+  #[test]
+  fn skip_trivia_matches_char_loop() {
+    fn reference(src: &str) -> usize {
+      let mut chars = src.char_indices().peekable();
+      while let Some(&(i, c)) = chars.peek() {
+        if c.is_ascii_whitespace() {
+          chars.next();
+        } else if src[i..].starts_with("//") {
+          chars.by_ref().find(|&(_, c)| c == '\n');
+        } else {
+          return i;
+        }
+      }
+      src.len()
+    }
+    for src in ["  \t\n x", "// a\n// b\n  y", "  // eof", "//\r\n\r\nz", "/ x", "\u{a0}x", ""] {
+      let mut p = TestParser::new(src);
+      p.skip_trivia();
+      assert_eq!(p.index, reference(src), "{:?}", src);
+    }
+    let mut p = TestParser::new(" \r\n\t y");
+    p.skip_spaces();
+    assert_eq!(p.index, 5);
+  }
+
+  #[test]
+  fn parse_enum_colors() {
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    enum Color { Red, Green, Blue }
+    const COLORS: &[(&str, Color)] = &[("red", Color::Red), ("green", Color::Green), ("blue", Color::Blue)];
+    assert_eq!(TestParser::new("red").parse_enum(COLORS).unwrap(), Color::Red);
+    assert_eq!(TestParser::new(" green").parse_enum(COLORS).unwrap(), Color::Green);
+    assert_eq!(TestParser::new("blue").parse_enum(COLORS).unwrap(), Color::Blue);
+    let mut p = TestParser::new(" redx");
+    let err = p.parse_enum(COLORS).unwrap_err();
+    assert!(plain(&err).contains("red | green | blue"));
+    assert_eq!(p.index, 1);
+  }
+
+  #[test]
+  fn parse_fixed_counts_items() {
+    let three = TestParser::new("(1, 2, 3)").parse_fixed(3, "(", ",", ")", |p| p.parse_u64()).unwrap();
+    assert_eq!(three, [1, 2, 3]);
+    let err = TestParser::new("(1, 2)").parse_fixed(3, "(", ",", ")", |p| p.parse_u64()).unwrap_err();
+    assert!(plain(&err).contains("expected 3 items, found 2"));
+    assert_eq!(err.span, (5, 6));
+    let err = TestParser::new("(1, 2, 3, 4)").parse_fixed(3, "(", ",", ")", |p| p.parse_u64()).unwrap_err();
+    assert!(plain(&err).contains("found more"));
+  }
+
+  #[test]
+  fn merge_takes_bounding_span() {
+    let merged = ParseError::new((5, 8), "a").merge(ParseError::new((2, 6), "b"));
+    assert_eq!(merged.span, (2, 8));
+    assert_eq!(merged.message, "a\nb");
+    let merged = ParseError::new((0, 1), "a").merge(ParseError::new((4, 9), "b"));
+    assert_eq!(merged.span, (0, 9));
+  }
+
+  #[test]
+  fn indented_block_stops_at_dedent() {
+    let src = "block:\n  a\n  b\nc";
+    let mut p = TestParser::new(src);
+    p.consume("block:").unwrap();
+    let items = p.parse_indented_block(0, |p| p.parse_name()).unwrap();
+    assert_eq!(items, ["a", "b"]);
+    assert_eq!(p.parse_name().unwrap(), "c");
+  }
+
+  #[test]
+  fn debug_state_format() {
+    let mut p = TestParser::new("ab λx(x)");
+    p.parse_name().unwrap();
+    p.skip_trivia();
+    assert_eq!(p.debug_state(), "index=3 next='λ' rest=\"x(x)\"");
+    let mut p = TestParser::new("x0123456789abcdefgh");
+    assert_eq!(p.debug_state(), "index=0 next='x' rest=\"0123456789abcdef\"...");
+    p.index = 19;
+    assert_eq!(p.debug_state(), "index=19 next=EOF");
+  }
+
+  #[test]
+  fn list_with_comments_between_items() {
+    let mut p = TestParser::new("[a, // second\n b]");
+    let items = p.parse_list_with_comments("[", ",", "]", |p| p.parse_name()).unwrap();
+    assert_eq!(items, [("a".to_owned(), vec![]), ("b".to_owned(), vec![" second".to_owned()])]);
+    assert!(p.is_eof());
+  }
+
+  #[test]
+  fn parse_path_segments() {
+    assert_eq!(TestParser::new("a/b").parse_path().unwrap(), ["a", "b"]);
+    let err = TestParser::new("a//b").parse_path().unwrap_err();
+    assert!(plain(&err).contains("empty path segment"));
+    assert_eq!(err.span, (2, 3));
+    assert!(TestParser::new("a/../b").parse_path().is_err());
+  }
+
+  #[test]
+  fn parse_u64_rejects_exponent() {
+    let err = TestParser::new("1e3").parse_u64().unwrap_err();
+    assert!(plain(&err).contains("integer literal cannot have exponent"));
+    assert_eq!(err.span, (1, 2));
+    let mut p = TestParser::new("1e");
+    assert_eq!(p.parse_u64().unwrap(), 1);
+    assert_eq!(TestParser::new("0x1e3").parse_u64().unwrap(), 0x1e3);
+  }
+
+  #[test]
+  fn positions_of_newlines() {
+    let mut p = TestParser::new("x\nab\nλ\n");
+    assert_eq!(p.positions(|c| c == '\n'), [1, 4, 7]);
+    p.advance_one();
+    assert_eq!(p.positions(|c| c == '\n'), [0, 3, 6]);
+    assert_eq!(p.index, 1);
+  }
+
+  #[derive(Debug)]
+  pub enum CustomError {
+    Parse(ParseError),
+    Negative,
+  }
+
+  impl From<ParseError> for CustomError {
+    fn from(err: ParseError) -> Self {
+      CustomError::Parse(err)
+    }
+  }
+
+  new_parser!(CustomParser, CustomError);
+
+  #[test]
+  fn custom_error_type_with_question_mark() {
+    fn natural(p: &mut CustomParser) -> Result<u64, CustomError> {
+      let sign = p.parse_sign();
+      let num = p.parse_u64()?;
+      if sign < 0 {
+        return Err(CustomError::Negative);
+      }
+      Ok(num)
+    }
+    assert_eq!(natural(&mut CustomParser::new("42")).unwrap(), 42);
+    assert!(matches!(natural(&mut CustomParser::new("-1")), Err(CustomError::Negative)));
+    assert!(matches!(natural(&mut CustomParser::new("x")), Err(CustomError::Parse(err)) if err.span == (0, 1)));
+  }
+
+  #[test]
+  fn quoted_string_max_length() {
+    assert_eq!(TestParser::new("\"abc\"").parse_quoted_string_max(3).unwrap(), "abc");
+    let err = TestParser::new(" \"a\\nbc\"").parse_quoted_string_max(2).unwrap_err();
+    assert!(plain(&err).contains("string literal too long (limit is 2 chars)"));
+    assert_eq!(err.span, (5, 6));
+  }
+
+  #[test]
+  fn consume_newline_variants() {
+    let mut p = TestParser::new("\n\r\n\r ");
+    p.consume_newline().unwrap();
+    assert_eq!(p.index, 1);
+    p.consume_newline().unwrap();
+    assert_eq!(p.index, 3);
+    p.consume_newline().unwrap();
+    assert_eq!(p.index, 4);
+    let err = p.consume_newline().unwrap_err();
+    assert!(plain(&err).contains("newline"));
+    assert_eq!(p.index, 4);
+  }
+
+  #[test]
+  fn class_match_ranges() {
+    assert_eq!(TestParser::new("q").parse_class_match("[a-z0-9]").unwrap(), 'q');
+    assert_eq!(TestParser::new("5").parse_class_match("[a-z0-9]").unwrap(), '5');
+    let mut p = TestParser::new("_");
+    assert!(p.parse_class_match("[a-z0-9]").is_err());
+    assert_eq!(p.index, 0);
+    assert_eq!(TestParser::new("_").parse_class_match("[^a-z0-9]").unwrap(), '_');
+  }
+
+  #[test]
+  fn peek_token_len_multibyte_name() {
+    let mut p = TestParser::new("  λé_x rest");
+    assert_eq!(p.peek_token_len(), 6);
+    assert_eq!(p.index, 0);
+    assert_eq!(TestParser::new("\"a b\" c").peek_token_len(), 5);
+    assert_eq!(TestParser::new("(x").peek_token_len(), 1);
+  }
+
+  #[test]
+  fn parse_object_duplicate_key() {
+    let entries = TestParser::new("{a: 1, \"b c\": 2,}").parse_object(|p| p.parse_u64()).unwrap();
+    assert_eq!(entries, [("a".to_owned(), 1), ("b c".to_owned(), 2)]);
+    let err = TestParser::new("{a: 1, a: 2}").parse_object(|p| p.parse_u64()).unwrap_err();
+    assert!(plain(&err).contains("duplicate key 'a'"));
+    assert_eq!(err.span, (7, 8));
+  }
+
+  #[test]
+  fn skip_to_matching_nested() {
+    let mut p = TestParser::new("(a (b) \")\" c) d");
+    p.consume("(").unwrap();
+    p.skip_to_matching('(', ')').unwrap();
+    assert_eq!(p.index, 13);
+    let mut p = TestParser::new("(a (b c");
+    p.consume("(").unwrap();
+    assert!(p.skip_to_matching('(', ')').is_err());
+  }
+
+  #[test]
+  fn current_line_mid_line() {
+    let mut p = TestParser::new("ab\ncdef\r\ngh");
+    p.index = 5;
+    assert_eq!(p.current_line(), "cdef");
+    assert_eq!(p.current_column(), 3);
+    p.index = 0;
+    assert_eq!(p.current_line(), "ab");
+    assert_eq!(p.current_column(), 1);
+  }
+
+  #[test]
+  fn parse_flag_spellings() {
+    for word in ["true", "yes", "on", "1", "TRUE", "Yes"] {
+      assert!(TestParser::new(word).parse_flag().unwrap(), "{}", word);
+    }
+    for word in ["false", "no", "off", "0", "Off"] {
+      assert!(!TestParser::new(word).parse_flag().unwrap(), "{}", word);
+    }
+    let mut p = TestParser::new(" truthy");
+    assert!(p.parse_flag().is_err());
+    assert_eq!(p.index, 1);
+  }
+
+  #[test]
+  fn recognize_prefixed_number() {
+    let mut p = TestParser::new("  0xFF_FF rest");
+    assert_eq!(p.recognize(|p| p.parse_u64()).unwrap(), "0xFF_FF");
+    assert_eq!(p.index, 9);
+  }
+
+  #[test]
+  fn collect_comment_spans_two_comments() {
+    let src = "// a\n  /* b */ x";
+    let mut p = TestParser::new(src);
+    let spans = p.collect_comment_spans();
+    assert_eq!(spans, [(0, 4), (7, 14)]);
+    assert_eq!(&src[spans[1].0..spans[1].1], "/* b */");
+    assert_eq!(p.index, 15);
+  }
+
+  #[test]
+  fn parse_decimal_scale() {
+    assert_eq!(TestParser::new("0.001").parse_decimal().unwrap(), (1, 3));
+    assert_eq!(TestParser::new("100").parse_decimal().unwrap(), (100, 0));
+    assert_eq!(TestParser::new("-3.14").parse_decimal().unwrap(), (-314, 2));
+    assert_eq!(TestParser::new("1_000.5").parse_decimal().unwrap(), (10005, 1));
+  }
+
+  #[test]
+  fn consume_one_of_returns_index() {
+    let mut p = TestParser::new(" <= x");
+    assert_eq!(p.consume_one_of(&["==", "<=", "<"]).unwrap(), 1);
+    assert_eq!(p.index, 3);
+    let err = p.consume_one_of(&["==", "<="]).unwrap_err();
+    assert!(plain(&err).contains("== | <="));
+  }
+
+  #[test]
+  fn quoted_char_escapes() {
+    assert_eq!(TestParser::new("'\\u{41}'").parse_quoted_char().unwrap(), 'A');
+    assert_eq!(TestParser::new("'\\''").parse_quoted_char().unwrap(), '\'');
+    let err = TestParser::new("'ab'").parse_quoted_char().unwrap_err();
+    assert!(plain(&err).contains("may only contain one character"));
+    let err = TestParser::new("'a, 'b'").parse_quoted_char().unwrap_err();
+    assert!(!plain(&err).contains("may only contain one character"));
+    assert_eq!(err.span, (2, 3));
+  }
+
+  #[test]
+  fn quoted_char_empty() {
+    let err = TestParser::new("''").parse_quoted_char().unwrap_err();
+    assert!(plain(&err).contains("empty character literal"));
+    assert_eq!(err.span, (0, 1));
+  }
+
+  #[test]
+  fn tuple_or_paren_shapes() {
+    let parse = |src: &str| TestParser::new(src).parse_tuple_or_paren(|p| p.parse_u64()).unwrap();
+    assert_eq!(parse("(1)"), TupleOrParen::Paren(1));
+    assert_eq!(parse("(1,)"), TupleOrParen::Tuple(vec![1]));
+    assert_eq!(parse("(1, 2)"), TupleOrParen::Tuple(vec![1, 2]));
+    assert_eq!(parse("()"), TupleOrParen::Tuple(vec![]));
+  }
+
+  #[test]
+  fn parse_uri_single_token() {
+    let mut p = TestParser::new(" https://a.com/x?y=1 next");
+    assert_eq!(p.parse_uri().unwrap(), "https://a.com/x?y=1");
+    assert_eq!(p.index, 20);
+    assert_eq!(TestParser::new("me@host.com)").parse_uri().unwrap(), "me@host.com");
+  }
+
+  #[test]
+  fn name_with_escapes() {
+    assert_eq!(TestParser::new("fo\\u{6F} x").parse_name_with_escapes().unwrap(), "foo");
+    let err = TestParser::new("a\\u{20}").parse_name_with_escapes().unwrap_err();
+    assert!(plain(&err).contains("not a valid name character"));
+    assert_eq!(err.span, (1, 2));
+  }
+
+  #[test]
+  fn check_line_length_span() {
+    let mut p = TestParser::new("abcdef\nxy");
+    p.consume_line();
+    let err = p.check_line_length(4).unwrap();
+    assert!(plain(&err).contains("line is longer than 4 characters"));
+    assert_eq!(err.span, (4, 6));
+    assert!(p.check_line_length(6).is_none());
+    p.index = 3;
+    assert!(p.check_line_length(1).is_none());
+  }
+
+  #[test]
+  fn parse_attributes_three_pairs() {
+    let mut p = TestParser::new("a=\"1\" b='2' c>");
+    let attrs = p.parse_attributes().unwrap();
+    assert_eq!(attrs, [("a".to_owned(), "1".to_owned()), ("b".to_owned(), "2".to_owned()), ("c".to_owned(), String::new())]);
+    assert!(p.starts_with(">"));
+  }
+
+  #[test]
+  fn step_budget_aborts_large_parse() {
+    let src = "a ".repeat(1000);
+    let mut p = TestParser::new(&src).with_budget(10);
+    let err = (0..1000).try_for_each(|_| p.parse_name().map(|_| ())).unwrap_err();
+    assert!(plain(&err).contains("parse budget exceeded"));
+    assert!(p.index <= 10);
+    let src = "x; ".repeat(1000);
+    let mut p = TestParser::new(&src).with_budget(10);
+    let err = (0..1000).try_for_each(|_| p.consume("x;")).unwrap_err();
+    assert!(plain(&err).contains("parse budget exceeded"));
+    let src = format!("// {}\nx", "λ".repeat(100));
+    let mut p = TestParser::new(&src).with_budget(10);
+    assert!(plain(&p.parse_name().unwrap_err()).contains("parse budget exceeded"));
+    assert_eq!(TestParser::new("abc").with_budget(3).parse_name().unwrap(), "abc");
+  }
+
+  #[test]
+  fn step_budget_never_truncates_tokens() {
+    let exceeded = |err: ParseError| plain(&err).contains("parse budget exceeded");
+    assert!(exceeded(TestParser::new("abc").with_budget(2).parse_name().unwrap_err()));
+    assert!(exceeded(TestParser::new("12345").with_budget(2).parse_u64().unwrap_err()));
+    assert!(exceeded(TestParser::new("a\nb\nc\nd\n").with_budget(3).layout_tokens(4).unwrap_err()));
+    assert!(exceeded(TestParser::new("ab {x} cd").with_budget(3).parse_brace_template().unwrap_err()));
+    assert!(exceeded(TestParser::new("a b c").with_budget(2).parse_inline_text(&[]).unwrap_err()));
+    let mut p = TestParser::new("##########").with_budget(1);
+    assert_eq!(p.consume_repeated("#"), 1);
+    assert_eq!(p.index, 1);
+    let mut p = TestParser::new("ab").with_budget(1);
+    assert!(!p.is_eof());
+    assert_eq!(p.peek_one(), Some('a'));
+  }
+
+  #[test]
+  fn span_overlap_and_containment() {
+    assert!(spans_overlap((0, 3), (2, 5)));
+    assert!(!spans_overlap((0, 2), (2, 4)));
+    assert!(!spans_overlap((0, 2), (5, 7)));
+    assert!(spans_overlap((3, 0), (2, 5)));
+    assert!(span_contains((0, 5), (1, 3)));
+    assert!(span_contains((0, 5), (0, 5)));
+    assert!(!span_contains((0, 5), (4, 6)));
+  }
+
+  #[test]
+  fn ident_maybe_quoted_flag() {
+    assert_eq!(TestParser::new("Name").parse_ident_maybe_quoted().unwrap(), ("Name".to_owned(), false));
+    assert_eq!(TestParser::new("\"My Name\"").parse_ident_maybe_quoted().unwrap(), ("My Name".to_owned(), true));
+    assert_eq!(TestParser::new("`a b`").parse_ident_maybe_quoted().unwrap(), ("a b".to_owned(), true));
+  }
+
+  #[test]
+  fn unadvance_over_multibyte() {
+    let mut p = TestParser::new("aλ");
+    p.advance_many(2);
+    assert_eq!(p.index, 3);
+    assert_eq!(p.unadvance_one(), Some('λ'));
+    assert_eq!(p.index, 1);
+    assert_eq!(p.unadvance_one(), Some('a'));
+    assert_eq!(p.unadvance_one(), None);
+    assert_eq!(p.index, 0);
+  }
+
+  #[test]
+  fn parse_range_forms() {
+    assert_eq!(TestParser::new("1..10").parse_range().unwrap(), (1, 10, false));
+    assert_eq!(TestParser::new("-5..=10").parse_range().unwrap(), (-5, 10, true));
+    let err = TestParser::new("1..").parse_range().unwrap_err();
+    assert!(plain(&err).contains("numeric digit"));
+    assert_eq!(TestParser::new("-9223372036854775808").parse_i64().unwrap(), i64::MIN);
+    assert!(TestParser::new("9223372036854775808").parse_i64().is_err());
+  }
+
+  #[test]
+  fn block_comment_kinds() {
+    let mut p = TestParser::new("/** outer /* inner */ */x");
+    assert_eq!(p.parse_block_comment_kind(), Some((true, " outer /* inner */ ")));
+    assert_eq!(p.index, 24);
+    assert_eq!(TestParser::new("/* a */").parse_block_comment_kind(), Some((false, " a ")));
+    assert_eq!(TestParser::new("/**/").parse_block_comment_kind(), Some((false, "")));
+    assert_eq!(TestParser::new("/*** a */").parse_block_comment_kind(), Some((false, "** a ")));
+  }
+
+  #[test]
+  fn skip_trivia_counts_newlines() {
+    let mut p = TestParser::new("a\n  // c\n\nb");
+    p.parse_name().unwrap();
+    assert_eq!(p.skip_trivia_counting_newlines(), 3);
+    assert_eq!(p.parse_name().unwrap(), "b");
+    let mut p = TestParser::new("a\n\nb");
+    p.parse_name().unwrap();
+    assert_eq!(p.skip_trivia_counting_newlines(), 2);
+  }
+
+  #[test]
+  fn hex_color_lengths() {
+    assert_eq!(TestParser::new("#fff").parse_hex_color().unwrap(), (255, 255, 255, 255));
+    assert_eq!(TestParser::new("#ffffff").parse_hex_color().unwrap(), (255, 255, 255, 255));
+    assert_eq!(TestParser::new("#ff000080").parse_hex_color().unwrap(), (255, 0, 0, 128));
+    let err = TestParser::new("#fffff").parse_hex_color().unwrap_err();
+    assert!(plain(&err).contains("unsupported hex color length 5"));
+    assert_eq!(err.span, (1, 6));
+  }
+
+  #[test]
+  fn iso_datetime_fields() {
+    assert_eq!(TestParser::new("2024-02-29T15:04:05").parse_iso_datetime().unwrap(), (2024, 2, 29, 15, 4, 5));
+    let err = TestParser::new("2024-13-01T00:00:00").parse_iso_datetime().unwrap_err();
+    assert!(plain(&err).contains("month 13 out of range"));
+    assert_eq!(err.span, (5, 7));
+    assert!(TestParser::new("2023-02-29T00:00:00").parse_iso_datetime().is_err());
+  }
+
+  #[test]
+  fn glob_patterns() {
+    let mut p = TestParser::new("src/**/*.rs next");
+    assert_eq!(p.parse_glob().unwrap(), "src/**/*.rs");
+    assert_eq!(p.index, 11);
+    assert_eq!(TestParser::new("a\\ b").parse_glob().unwrap(), "a b");
+    assert_eq!(TestParser::new("a\\*").parse_glob().unwrap(), "a\\*");
+  }
+
+  #[test]
+  fn column_with_tabs_expands() {
+    let mut p = TestParser::new("x\n  \ty");
+    p.index = 5;
+    assert_eq!(p.column_with_tabs(8), 8);
+    assert_eq!(p.column_with_tabs(4), 4);
+    p.index = 3;
+    assert_eq!(p.column_with_tabs(8), 1);
+  }
+
+  #[test]
+  fn sep_by1_counts() {
+    assert_eq!(TestParser::new("a").parse_sep_by1(|p| p.parse_name(), ",").unwrap(), ["a"]);
+    let mut p = TestParser::new("a, b ,c ;");
+    assert_eq!(p.parse_sep_by1(|p| p.parse_name(), ",").unwrap(), ["a", "b", "c"]);
+    assert_eq!(p.index, 7);
+    assert!(TestParser::new("").parse_sep_by1(|p| p.parse_name(), ",").is_err());
+  }
+
+  #[test]
+  fn unary_operator_nesting() {
+    type Op = (&'static str, fn(String) -> String);
+    let prefix: &[Op] = &[("!", |x| format!("!({})", x)), ("-", |x| format!("-({})", x))];
+    let postfix: &[Op] = &[("!", |x| format!("({})!", x))];
+    assert_eq!(TestParser::new("!-x").parse_unary(prefix, &[], |p| p.parse_name()).unwrap(), "!(-(x))");
+    assert_eq!(TestParser::new("x!").parse_unary(&[], postfix, |p| p.parse_name()).unwrap(), "(x)!");
+    assert_eq!(TestParser::new("-x!").parse_unary(prefix, postfix, |p| p.parse_name()).unwrap(), "-((x)!)");
+  }
+
+  #[test]
+  fn word_boundary_positions() {
+    let mut p = TestParser::new("foo bar");
+    assert!(p.at_word_boundary());
+    p.index = 1;
+    assert!(!p.at_word_boundary());
+    p.index = 3;
+    assert!(p.at_word_boundary());
+    p.index = 7;
+    assert!(p.at_word_boundary());
+    let mut p = TestParser::new(" é");
+    p.index = 1;
+    assert!(!p.at_word_boundary());
+  }
+
+  #[test]
+  fn block_string_dedents() {
+    let src = "\"\"\"\n    a\n      b\n    c\n    \"\"\"";
+    let mut p = TestParser::new(src);
+    assert_eq!(p.parse_block_string().unwrap(), "a\n  b\nc");
+    assert!(p.is_eof());
+    let err = TestParser::new("\"\"\"\n  a\n b\n  \"\"\"").parse_block_string().unwrap_err();
+    assert!(plain(&err).contains("indented less than the closing"));
+  }
+
+  #[test]
+  fn dedup_errors_collapses_repeats() {
+    let errors = vec![ParseError::new((4, 5), "b"), ParseError::new((1, 2), "a"), ParseError::new((4, 5), "b"), ParseError::new((4, 5), "c")];
+    let deduped = dedup_errors(errors);
+    let found = deduped.iter().map(|err| (err.span, err.message.as_str())).collect::<Vec<_>>();
+    assert_eq!(found, [((1, 2), "a"), ((4, 5), "b"), ((4, 5), "c")]);
+  }
+
+  #[test]
+  fn fenced_front_matter() {
+    let mut p = TestParser::new("---\ntitle: x\ntags: y\n---\nbody");
+    p.consume("---").unwrap();
+    assert_eq!(p.parse_fenced_block("---").unwrap(), "title: x\ntags: y");
+    assert_eq!(p.consume_line(), "body");
+    let mut p = TestParser::new("---\nx\n");
+    p.consume("---").unwrap();
+    assert!(p.parse_fenced_block("---").is_err());
+  }
+
+  #[test]
+  fn optional_annotation() {
+    let mut p = TestParser::new("x: Nat");
+    p.parse_name().unwrap();
+    assert_eq!(p.parse_optional_annotation(|p| p.parse_name()).unwrap(), Some("Nat".to_owned()));
+    let mut p = TestParser::new("x = 1");
+    p.parse_name().unwrap();
+    assert_eq!(p.parse_optional_annotation(|p| p.parse_name()).unwrap(), None);
+    assert_eq!(p.index, 1);
+    let mut p = TestParser::new("x::y");
+    p.parse_name().unwrap();
+    assert_eq!(p.parse_optional_annotation(|p| p.parse_name()).unwrap(), None);
+  }
+
+  #[test]
+  fn items_sep_comma_or_newline() {
+    let mut p = TestParser::new("[a, b\n  c\n\n  d,]");
+    p.consume("[").unwrap();
+    assert_eq!(p.parse_items_sep_comma_or_newline(|p| p.parse_name(), "]").unwrap(), ["a", "b", "c", "d"]);
+    let mut p = TestParser::new("[a b]");
+    p.consume("[").unwrap();
+    let err = p.parse_items_sep_comma_or_newline(|p| p.parse_name(), "]").unwrap_err();
+    assert!(plain(&err).contains("',' or newline"));
+  }
+
+  #[test]
+  fn brace_template_parts() {
+    let parts = TestParser::new("a {x} b {{lit}}").parse_brace_template().unwrap();
+    assert_eq!(parts, [
+      TemplatePart::Literal("a ".to_owned()),
+      TemplatePart::Placeholder("x".to_owned()),
+      TemplatePart::Literal(" b {lit}".to_owned()),
+    ]);
+    let err = TestParser::new("a } b").parse_brace_template().unwrap_err();
+    assert!(plain(&err).contains("unmatched '}'"));
+    assert_eq!(err.span, (2, 3));
+  }
+
+  #[test]
+  fn quantity_units() {
+    assert_eq!(TestParser::new("10px").parse_quantity().unwrap(), (10.0, "px".to_owned()));
+    assert_eq!(TestParser::new("1.5em").parse_quantity().unwrap(), (1.5, "em".to_owned()));
+    assert_eq!(TestParser::new("50%").parse_quantity().unwrap(), (50.0, "%".to_owned()));
+    assert_eq!(TestParser::new("42").parse_quantity().unwrap(), (42.0, String::new()));
+    assert_eq!(TestParser::new("-2e1vh").parse_quantity().unwrap(), (-20.0, "vh".to_owned()));
+  }
+
+  #[test]
+  fn qualified_name_segments() {
+    assert_eq!(TestParser::new("std::collections::HashMap").parse_qualified_name().unwrap(), ["std", "collections", "HashMap"]);
+    let err = TestParser::new("std::").parse_qualified_name().unwrap_err();
+    assert!(plain(&err).contains("expected a segment after '::'"));
+    assert_eq!(err.span, (5, 6));
+  }
+
+  #[test]
+  fn inline_text_collapses_whitespace() {
+    let mut p = TestParser::new("  a   b  <x>");
+    assert_eq!(p.parse_inline_text(&['<']).unwrap(), "a b");
+    assert!(p.starts_with("<"));
+    assert_eq!(TestParser::new(" a\n\tb ").parse_inline_text(&['<']).unwrap(), "a b");
+  }
+
+  #[test]
+  fn unicode_escape_surrogate_and_max() {
+    let err = TestParser::new("\\u{D800}").parse_char().unwrap_err();
+    assert!(plain(&err).contains("lone surrogate U+D800 is not a valid character"));
+    let err = TestParser::new("\\u{110000}").parse_char().unwrap_err();
+    assert!(plain(&err).contains("U+110000 exceeds the maximum (U+10FFFF)"));
+    assert_eq!(TestParser::new("\\u{10FFFF}").parse_char().unwrap(), '\u{10FFFF}');
+  }
+
+  #[test]
+  fn grouped_decimal_separators() {
+    assert_eq!(TestParser::new("-1,234.56").parse_grouped_decimal().unwrap(), -1234.56);
+    assert_eq!(TestParser::new("1234").parse_grouped_decimal().unwrap(), 1234.0);
+    let err = TestParser::new("1,23").parse_grouped_decimal().unwrap_err();
+    assert!(plain(&err).contains("exactly 3 digits"));
+    assert_eq!(err.span, (2, 4));
+  }
+
+  #[test]
+  fn regex_literal_with_escaped_slash() {
+    let mut p = TestParser::new("/a\\/b/gi x");
+    assert_eq!(p.parse_regex_literal().unwrap(), ("a\\/b".to_owned(), "gi".to_owned()));
+    assert_eq!(p.index, 8);
+    assert_eq!(TestParser::new("/[/]/").parse_regex_literal().unwrap(), ("[/]".to_owned(), String::new()));
+    assert!(TestParser::new("/ab\n/").parse_regex_literal().is_err());
+  }
+
+  #[test]
+  fn tagged_mismatch_names_both_tags() {
+    let inner = |p: &mut TestParser, _: &str| p.parse_name();
+    assert_eq!(TestParser::new("<b>x</b>").parse_tagged(inner).unwrap(), "x");
+    let err = TestParser::new("<b>x</i>").parse_tagged(inner).unwrap_err();
+    assert!(plain(&err).contains("closing tag </i> does not match opening tag <b>"));
+    assert_eq!(err.span, (6, 7));
+  }
+
+  #[test]
+  fn based_int_bases() {
+    assert_eq!(TestParser::new("16#FF").parse_based_int().unwrap(), 255);
+    assert_eq!(TestParser::new("2r1010").parse_based_int().unwrap(), 10);
+    let err = TestParser::new("37#1").parse_based_int().unwrap_err();
+    assert!(plain(&err).contains("invalid base 37"));
+    assert_eq!(err.span, (0, 2));
+    let err = TestParser::new("8#19").parse_based_int().unwrap_err();
+    assert!(plain(&err).contains("invalid digit '9' for base 8"));
+  }
+
+  #[test]
+  fn trivia_classified_comments() {
+    let mut p = TestParser::new("a // after a\n// before b\nb");
+    let mut comments = Vec::new();
+    p.parse_name().unwrap();
+    p.skip_trivia_classified(&mut comments);
+    assert_eq!(comments, [(CommentKind::Trailing, " after a".to_owned()), (CommentKind::Leading, " before b".to_owned())]);
+    assert_eq!(p.parse_name().unwrap(), "b");
+  }
+
+  #[test]
+  fn binops_associativity() {
+    const OPS: &[(&str, u8, Assoc)] = &[("-", 1, Assoc::Left), ("^", 2, Assoc::Right)];
+    let atom = |p: &mut TestParser| p.parse_name();
+    let group = |l: String, op: &str, r: String| format!("({} {} {})", l, op, r);
+    assert_eq!(TestParser::new("2 ^ 3 ^ 2").parse_binops(atom, OPS, group).unwrap(), "(2 ^ (3 ^ 2))");
+    assert_eq!(TestParser::new("a - b - c").parse_binops(atom, OPS, group).unwrap(), "((a - b) - c)");
+    assert_eq!(TestParser::new("a - b ^ c").parse_binops(atom, OPS, group).unwrap(), "(a - (b ^ c))");
+  }
+
+  #[test]
+  fn skip_token_recovery() {
+    let mut p = TestParser::new("12x3 next");
+    assert_eq!(p.parse_u64().unwrap(), 12);
+    assert_eq!(p.index, 2);
+    p.skip_token();
+    assert_eq!(p.index, 4);
+    assert_eq!(p.parse_name().unwrap(), "next");
+    let mut p = TestParser::new("  \"a b\" (");
+    p.skip_token();
+    assert_eq!(p.index, 7);
+    p.skip_token();
+    assert!(p.is_eof());
+    p.skip_token();
+    assert!(p.is_eof());
+    let mut p = TestParser::new("\"a\\q b\" next");
+    assert!(p.parse_quoted_string().is_err());
+    p.skip_token();
+    assert_eq!(p.parse_name().unwrap(), "next");
+    let mut p = TestParser::new(" λλ").with_budget(2);
+    p.skip_token();
+    assert_eq!(p.index, 3);
+    assert_eq!(p.position().col, 3);
+  }
+
+  #[test]
+  fn delimited_comment_haskell_style() {
+    let mut p = TestParser::new("{- a {- b -} c -}x");
+    assert_eq!(p.parse_delimited_comment("{-", "-}", true), Some(" a {- b -} c "));
+    assert_eq!(p.index, 17);
+    let mut p = TestParser::new("{- a {- b -} c -}x");
+    assert_eq!(p.parse_delimited_comment("{-", "-}", false), Some(" a {- b "));
+    assert_eq!(TestParser::new("{- a {- b -}").parse_delimited_comment("{-", "-}", true), None);
+    assert_eq!(TestParser::new("x -}").parse_delimited_comment("", "-}", true), None);
+    assert_eq!(TestParser::new("{- x").parse_delimited_comment("{-", "", false), None);
+  }
+
+  #[test]
+  fn oneline_error() {
+    let err = TestParser::new("ab (").parse_tagged(|p, _| p.parse_name()).unwrap_err();
+    assert_eq!(err.oneline(), "parse error at 0: expected <");
+    let mut p = TestParser::new("{a: 1, a: 2}");
+    let err = p.parse_object(|p| p.parse_u64()).unwrap_err();
+    assert_eq!(err.oneline(), "parse error at 7: expected unique key (duplicate key 'a')");
+    assert_eq!(ParseError::new((3, 4), "custom").oneline(), "parse error at 3: custom");
+  }
+
+  #[test]
+  fn hashbracket_attributes() {
+    let mut p = TestParser::new("#[inline] #[cfg(any(a, b))]  fn");
+    assert_eq!(p.parse_attributes_hashbracket().unwrap(), ["inline", "cfg(any(a, b))"]);
+    assert_eq!(p.index, 27);
+    assert!(TestParser::new("#[a").parse_attributes_hashbracket().is_err());
+  }
+
+  #[test]
+  fn quoted_string_mapped_offsets() {
+    let mapped = TestParser::new("\"a\\nb\"").parse_quoted_string_mapped().unwrap();
+    assert_eq!(mapped, [('a', 1), ('\n', 2), ('b', 4)]);
+    let mapped = TestParser::new("\"λ\\u{41}\"").parse_quoted_string_mapped().unwrap();
+    assert_eq!(mapped, [('λ', 1), ('A', 3)]);
+  }
+
+  #[test]
+  fn layout_tokens_balanced() {
+    use LayoutToken::*;
+    let src = "a\n  b\n    c\n\n  d\ne";
+    let tokens = TestParser::new(src).layout_tokens(4).unwrap();
+    assert_eq!(tokens, [
+      Content("a"), Newline,
+      Indent, Content("b"), Newline,
+      Indent, Content("c"), Newline,
+      Dedent, Content("d"), Newline,
+      Dedent, Content("e"), Newline,
+    ]);
+    let tokens = TestParser::new("a\n  b\n    c").layout_tokens(4).unwrap();
+    assert_eq!(tokens.iter().filter(|t| **t == Indent).count(), tokens.iter().filter(|t| **t == Dedent).count());
+    let err = TestParser::new("a\n    b\n  c").layout_tokens(4).unwrap_err();
+    assert!(plain(&err).contains("dedent to column 2 does not match any outer indentation level"));
+    assert_eq!(err.span, (10, 11));
+  }
 }